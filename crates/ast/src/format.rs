@@ -0,0 +1,365 @@
+use crate::{trimmed_range, BandExpr, BinaryExpr, Dice, Expr, ParenExpr, Root, Set, TaggedExpr, UnaryExpr};
+use syntax::{SyntaxElement, SyntaxKind, SyntaxNode, SyntaxToken};
+
+
+// How tightly each kind of expression binds, matching the binding powers of the parser. An
+// expression can be written without parentheses wherever its binding power is at least the
+// minimum allowed there.
+const BAND: u8 = 0;
+const VERSUS: u8 = 1;
+const SUM: u8 = 3;
+const PRODUCT: u8 = 5;
+const PREFIX: u8 = 7;
+const TAGGED: u8 = 8;
+const ATOM: u8 = 9;
+
+
+/// Prints `root` in a normalised form: one space around binary operators and `vs`, lowercase
+/// operators, an explicit count on every dice (`d20` is written `1d20`), and no redundant
+/// parentheses. Formatting an expression twice gives the same result as formatting it once.
+///
+/// Trees with errors are formatted as far as they can be, and anything the parser couldn't make
+/// sense of is kept as it was written.
+pub fn format(root: &Root) -> String {
+    let mut f = Formatter { out: String::new(), text: root.text() };
+    // What follows an error is only separated from it if it was written that way, so that
+    // `kh1` in `((1, 2))kh1` stays together.
+    let mut after_error = false;
+
+    for element in root.0.children_with_tokens() {
+        match element {
+            SyntaxElement::Node(node) if node.kind() == SyntaxKind::Error => {
+                f.error(&node);
+                after_error = true;
+            }
+            SyntaxElement::Node(node) => {
+                if after_error {
+                    f.space_if_written(&node);
+                } else {
+                    f.space();
+                }
+
+                f.child(node, BAND);
+                after_error = false;
+            }
+            SyntaxElement::Token(token) => f.token(&token),
+        }
+    }
+
+    f.out.trim_end().to_string()
+}
+
+
+struct Formatter {
+    out: String,
+    /// The source text, to tell where errors were separated from what came before them.
+    text: String,
+}
+
+impl Formatter {
+    fn expr(&mut self, expr: &Expr, min_binding_power: u8) {
+        match expr {
+            Expr::ParenExpr(paren) => match paren.redundant_inner() {
+                Some(inner) if binding_power(&inner) >= min_binding_power => {
+                    self.expr(&inner, min_binding_power);
+                }
+                _ => self.paren(paren),
+            },
+            Expr::BandExpr(band) => self.band_expr(band),
+            Expr::BinaryExpr(binary) => self.binary(binary),
+            Expr::Dice(dice) => self.dice(dice),
+            Expr::Literal(literal) => self.tokens(&literal.0),
+            Expr::Set(set) => self.set(set),
+            Expr::TaggedExpr(tagged) => self.tagged(tagged),
+            Expr::UnaryExpr(unary) => self.unary(unary),
+        }
+    }
+
+    fn band_expr(&mut self, band: &BandExpr) {
+        let band_count = band.bands().count();
+        let mut bands = 0;
+
+        for element in band.0.children_with_tokens() {
+            match element {
+                SyntaxElement::Node(node) => match node.kind() {
+                    SyntaxKind::Band => {
+                        self.tokens(&node);
+                        bands += 1;
+                    }
+                    _ => self.child(node, VERSUS),
+                },
+                SyntaxElement::Token(token) => match token.kind() {
+                    SyntaxKind::Arrow => self.out.push_str(" -> "),
+                    // A trailing comma is dropped.
+                    SyntaxKind::Comma if bands == band_count => {}
+                    SyntaxKind::Comma => self.out.push_str(", "),
+                    SyntaxKind::RBrace => self.close("}"),
+                    _ => self.token(&token),
+                },
+            }
+        }
+    }
+
+    fn binary(&mut self, binary: &BinaryExpr) {
+        let (lhs, rhs) = match binary.op().map(|op| op.kind()) {
+            Some(SyntaxKind::Versus) => (VERSUS, VERSUS + 1),
+            Some(SyntaxKind::Plus) | Some(SyntaxKind::Minus) => (SUM, SUM + 1),
+            _ => (PRODUCT, PRODUCT + 1),
+        };
+        let mut operands = 0;
+
+        for element in binary.0.children_with_tokens() {
+            match element {
+                SyntaxElement::Node(node) if node.kind() == SyntaxKind::Error => self.error(&node),
+                SyntaxElement::Node(node) => {
+                    self.space();
+                    self.child(node, if operands == 0 { lhs } else { rhs });
+                    operands += 1;
+                }
+                SyntaxElement::Token(token) if token.kind() != SyntaxKind::Whitespace => {
+                    self.space();
+                    self.token(&token);
+                }
+                SyntaxElement::Token(_) => {}
+            }
+        }
+    }
+
+    fn dice(&mut self, dice: &Dice) {
+        let text = dice.0.first_token().unwrap().text().to_lowercase();
+
+        if text.starts_with('d') {
+            self.out.push('1');
+        }
+        self.out.push_str(&text);
+
+        for op in dice.ops() {
+            self.tokens(&op.0);
+        }
+    }
+
+    fn paren(&mut self, paren: &ParenExpr) {
+        for element in paren.0.children_with_tokens() {
+            match element {
+                SyntaxElement::Node(node) => self.child(node, VERSUS),
+                SyntaxElement::Token(token) => match token.kind() {
+                    SyntaxKind::LParen => self.out.push('('),
+                    SyntaxKind::RParen => self.close(")"),
+                    _ => self.token(&token),
+                },
+            }
+        }
+    }
+
+    fn set(&mut self, set: &Set) {
+        let item_count = set.items().count();
+        let mut items = 0;
+
+        for element in set.0.children_with_tokens() {
+            match element {
+                SyntaxElement::Node(node) => match node.kind() {
+                    SyntaxKind::SetOp => self.tokens(&node),
+                    SyntaxKind::Error => self.error(&node),
+                    _ => {
+                        self.child(node, VERSUS);
+                        items += 1;
+                    }
+                },
+                SyntaxElement::Token(token) => match token.kind() {
+                    SyntaxKind::LParen => self.out.push('('),
+                    SyntaxKind::RParen => self.close(")"),
+                    // A set of one item needs its trailing comma, but other sets don't.
+                    SyntaxKind::Comma if items == item_count && item_count != 1 => {}
+                    SyntaxKind::Comma => self.out.push_str(", "),
+                    _ => self.token(&token),
+                },
+            }
+        }
+    }
+
+    fn tagged(&mut self, tagged: &TaggedExpr) {
+        for element in tagged.0.children_with_tokens() {
+            match element {
+                SyntaxElement::Node(node) => self.child(node, ATOM),
+                SyntaxElement::Token(token) => self.token(&token),
+            }
+        }
+    }
+
+    fn unary(&mut self, unary: &UnaryExpr) {
+        for element in unary.0.children_with_tokens() {
+            match element {
+                SyntaxElement::Node(node) => self.child(node, PREFIX),
+                SyntaxElement::Token(token) => self.token(&token),
+            }
+        }
+    }
+
+    /// Formats an operand, or writes it as it was if it is an error.
+    fn child(&mut self, node: SyntaxNode, min_binding_power: u8) {
+        match Expr::cast(node.clone()) {
+            Some(expr) => self.expr(&expr, min_binding_power),
+            None => self.error(&node),
+        }
+    }
+
+    /// Writes every token of `node` without whitespace, like `kh3` or `<=6: miss`.
+    fn tokens(&mut self, node: &SyntaxNode) {
+        for element in node.children_with_tokens() {
+            match element {
+                SyntaxElement::Node(node) if node.kind() == SyntaxKind::Error => self.error(&node),
+                SyntaxElement::Node(node) => self.tokens(&node),
+                SyntaxElement::Token(token) => self.token(&token),
+            }
+        }
+    }
+
+    fn token(&mut self, token: &SyntaxToken) {
+        match token.kind() {
+            SyntaxKind::Whitespace => {}
+            SyntaxKind::Label => {
+                let label = token.text().trim_start_matches(':').trim_start();
+                self.out.push_str(": ");
+                self.out.push_str(label);
+            }
+            SyntaxKind::Tag | SyntaxKind::Error => self.out.push_str(token.text()),
+            _ => self.out.push_str(&token.text().to_lowercase()),
+        }
+    }
+
+    /// Writes `node` as it was written, after a space if there was whitespace before it.
+    fn error(&mut self, node: &SyntaxNode) {
+        self.space_if_written(node);
+        self.out.push_str(node.text().to_string().trim());
+    }
+
+    /// Writes a space if there was whitespace before `node` in the source text.
+    fn space_if_written(&mut self, node: &SyntaxNode) {
+        let start = usize::from(trimmed_range(node).start());
+
+        if self.text[..start].ends_with(char::is_whitespace) {
+            self.space();
+        }
+    }
+
+    fn space(&mut self) {
+        if !self.out.is_empty() && !self.out.ends_with(&[' ', '(', '{'][..]) {
+            self.out.push(' ');
+        }
+    }
+
+    fn close(&mut self, delimiter: &str) {
+        self.out.truncate(self.out.trim_end().len());
+        self.out.push_str(delimiter);
+    }
+}
+
+
+impl ParenExpr {
+    /// The expression inside the parentheses, with any more parentheses around it removed, if
+    /// the parentheses are complete and hold nothing else.
+    fn redundant_inner(&self) -> Option<Expr> {
+        let closed = self.0
+            .children_with_tokens()
+            .filter_map(SyntaxElement::into_token)
+            .any(|token| token.kind() == SyntaxKind::RParen);
+        let complete = closed && self.0.children().all(|node| Expr::cast(node).is_some());
+
+        match self.expr()? {
+            Expr::ParenExpr(paren) if complete => paren.redundant_inner(),
+            inner if complete => Some(inner),
+            _ => None,
+        }
+    }
+}
+
+fn binding_power(expr: &Expr) -> u8 {
+    match expr {
+        Expr::BandExpr(_) => BAND,
+        Expr::BinaryExpr(binary) => match binary.op().map(|op| op.kind()) {
+            Some(SyntaxKind::Versus) => VERSUS,
+            Some(SyntaxKind::Plus) | Some(SyntaxKind::Minus) => SUM,
+            _ => PRODUCT,
+        },
+        Expr::UnaryExpr(_) => PREFIX,
+        Expr::TaggedExpr(_) => TAGGED,
+        Expr::ParenExpr(paren) => match paren.redundant_inner() {
+            Some(inner) => binding_power(&inner),
+            None => ATOM,
+        },
+        Expr::Dice(_) | Expr::Literal(_) | Expr::Set(_) => ATOM,
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check(input: &str, expected: &str) {
+        let root = Root::cast(parser::parse(input).syntax()).unwrap();
+        let formatted = format(&root);
+
+        assert_eq!(formatted, expected);
+
+        let root = Root::cast(parser::parse(&formatted).syntax()).unwrap();
+        assert_eq!(format(&root), formatted, "formatting {:?} again changed it", formatted);
+    }
+
+    #[test]
+    fn spaces_operators() {
+        check("1d20+5vs 2d10 *2", "1d20 + 5 vs 2d10 * 2");
+        check("  -  3 ", "-3");
+    }
+
+    #[test]
+    fn writes_the_count_of_dice() {
+        check("d20 + d%", "1d20 + 1d%");
+    }
+
+    #[test]
+    fn removes_redundant_parentheses() {
+        check("((1d20)) + (2 * 3)", "1d20 + 2 * 3");
+        check("(1 + 2) * ((3 - 4))", "(1 + 2) * (3 - 4)");
+        check("1 - (2 - 3) - (4 + 5)", "1 - (2 - 3) - (4 + 5)");
+        check("(1 - 2) - 3", "1 - 2 - 3");
+        check("-(1d6) - (-2)", "-1d6 - -2");
+        check("-(1 + 2)", "-(1 + 2)");
+        check("(1 vs 2) + 3", "(1 vs 2) + 3");
+    }
+
+    #[test]
+    fn keeps_parentheses_that_tags_need() {
+        check("(1d6)[fire] + (1d4 + 1)[cold]", "1d6[fire] + (1d4 + 1)[cold]");
+        check("(-1d6)[fire]", "(-1d6)[fire]");
+    }
+
+    #[test]
+    fn sets() {
+        check("( 4d6 kh3,(1d20) ,3, )kh1", "(4d6kh3, 1d20, 3)kh1");
+        check("((2d20),)", "(2d20,)");
+        check("( )", "()");
+    }
+
+    #[test]
+    fn bands() {
+        check(
+            "2d6+2->{ <=6:miss,7 .. 9 : partial , >=10:  hit, }",
+            "2d6 + 2 -> {<=6: miss, 7..9: partial, >=10: hit}",
+        );
+    }
+
+    #[test]
+    fn keeps_errors_as_they_were_written() {
+        check("(1+", "(1 +");
+        check("1d20 +", "1d20 +");
+        check("(1 + 2", "(1 + 2");
+        check("1d20 -> {>10}", "1d20 -> {>10}");
+        check("(1, +, 2)kh1", "(1, +, 2)kh1");
+        check("1d6[fire][cold]  )", "1d6[fire][cold] )");
+        check("1d20 -> {<10 miss}", "1d20 -> {<10 miss}");
+        check("4d6k+1", "4d6k + 1");
+        check("1d6rx", "1d6rx");
+        check("((1,2))kh1", "(1, 2)kh1");
+    }
+}
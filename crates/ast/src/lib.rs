@@ -1,7 +1,24 @@
+pub mod format;
 pub mod validation;
 
 
 use syntax::{SyntaxElement, SyntaxKind, SyntaxNode, SyntaxToken};
+use text_size::TextRange;
+
+
+/// The range of `node`'s text, leaving out any whitespace around it.
+fn trimmed_range(node: &SyntaxNode) -> TextRange {
+    let mut tokens = node
+        .descendants_with_tokens()
+        .filter_map(SyntaxElement::into_token)
+        .filter(|token| token.kind() != SyntaxKind::Whitespace)
+        .map(|token| token.text_range());
+
+    match tokens.next() {
+        Some(first) => first.cover(tokens.last().unwrap_or(first)),
+        None => TextRange::empty(node.text_range().start()),
+    }
+}
 
 
 #[derive(Debug)]
@@ -19,33 +36,110 @@ impl Root {
     pub fn expr(&self) -> Option<Expr> {
         self.0.children().find_map(Expr::cast)
     }
+
+    /// The source text the tree was parsed from, whitespace and all.
+    pub fn text(&self) -> String {
+        self.0.text().to_string()
+    }
 }
 
 
 #[derive(Debug)]
 pub enum Expr {
+    BandExpr(BandExpr),
     BinaryExpr(BinaryExpr),
     Dice(Dice),
     Literal(Literal),
     ParenExpr(ParenExpr),
     Set(Set),
+    TaggedExpr(TaggedExpr),
     UnaryExpr(UnaryExpr),
 }
 
 impl Expr {
     pub fn cast(node: SyntaxNode) -> Option<Self> {
         let result = match node.kind() {
+            SyntaxKind::BandExpr => Self::BandExpr(BandExpr(node)),
             SyntaxKind::InfixExpr => Self::BinaryExpr(BinaryExpr(node)),
             SyntaxKind::DiceExpr => Self::Dice(Dice(node)),
             SyntaxKind::Literal => Self::Literal(Literal(node)),
             SyntaxKind::ParenExpr => Self::ParenExpr(ParenExpr(node)),
             SyntaxKind::SetExpr => Self::Set(Set(node)),
+            SyntaxKind::TaggedExpr => Self::TaggedExpr(TaggedExpr(node)),
             SyntaxKind::PrefixExpr => Self::UnaryExpr(UnaryExpr(node)),
             _ => return None,
         };
 
         Some(result)
     }
+
+    /// The range of the expression in the source text, leaving out any whitespace around it.
+    pub fn range(&self) -> TextRange {
+        let node = match self {
+            Self::BandExpr(expr) => &expr.0,
+            Self::BinaryExpr(expr) => &expr.0,
+            Self::Dice(expr) => &expr.0,
+            Self::Literal(expr) => &expr.0,
+            Self::ParenExpr(expr) => &expr.0,
+            Self::Set(expr) => &expr.0,
+            Self::TaggedExpr(expr) => &expr.0,
+            Self::UnaryExpr(expr) => &expr.0,
+        };
+
+        trimmed_range(node)
+    }
+}
+
+
+#[derive(Debug)]
+pub struct BandExpr(SyntaxNode);
+
+impl BandExpr {
+    pub fn expr(&self) -> Option<Expr> {
+        self.0.children().find_map(Expr::cast)
+    }
+
+    pub fn bands(&self) -> impl Iterator<Item=Band> {
+        self.0.children()
+            .filter(|node| node.kind() == SyntaxKind::Band)
+            .map(Band)
+    }
+}
+
+
+/// One arm of a `BandExpr`, like `<=6: miss` or `7..9: partial`.
+#[derive(Debug)]
+pub struct Band(SyntaxNode);
+
+impl Band {
+    /// The comparison (`<`, `<=`, `>`, `>=`) or range (`..`) operator, if the band has one.
+    pub fn op(&self) -> Option<SyntaxToken> {
+        self.0
+            .children_with_tokens()
+            .filter_map(SyntaxElement::into_token)
+            .find(|token| matches!(
+                token.kind(),
+                SyntaxKind::Less
+                    | SyntaxKind::LessEq
+                    | SyntaxKind::Greater
+                    | SyntaxKind::GreaterEq
+                    | SyntaxKind::DotDot,
+            ))
+    }
+
+    pub fn bounds(&self) -> impl Iterator<Item=Literal> {
+        self.0.children()
+            .filter_map(|node| Literal::cast(&node))
+    }
+
+    /// The band's label, without the leading colon.
+    pub fn label(&self) -> Option<String> {
+        self.0
+            .children_with_tokens()
+            .filter_map(SyntaxElement::into_token)
+            .find(|token| token.kind() == SyntaxKind::Label)
+            .map(|token| token.text().trim_start_matches(':').trim_start().to_string())
+    }
 }
 
 
@@ -67,7 +161,11 @@ impl BinaryExpr {
             .filter_map(SyntaxElement::into_token)
             .find(|token| matches!(
                 token.kind(),
-                SyntaxKind::Plus | SyntaxKind::Minus | SyntaxKind::Star | SyntaxKind::Slash,
+                SyntaxKind::Plus
+                    | SyntaxKind::Minus
+                    | SyntaxKind::Star
+                    | SyntaxKind::Slash
+                    | SyntaxKind::Versus,
             ))
     }
 }
@@ -85,6 +183,10 @@ impl Dice {
         }
     }
 
+    /// The number of dice to roll, which is 1 if the count is left implicit (`d20`).
+    ///
+    /// A count can be zero (`0d6`) but never negative: `-2d6` is the negation of a roll of
+    /// `2d6`, so its `count` is 2.
     pub fn count(&self) -> Option<u64> {
         let split_text = self.0.first_token().unwrap();
         let mut split_text = split_text.text().split('d');
@@ -99,7 +201,7 @@ impl Dice {
 
     pub fn sides(&self) -> Option<u64> {
         let sides_text = self.0.first_token().unwrap();
-        let sides_text = sides_text.text().split('d').last().unwrap();
+        let sides_text = sides_text.text().split('d').next_back().unwrap();
 
         if sides_text == "%" {
             Some(100)
@@ -113,6 +215,11 @@ impl Dice {
             .filter(|node| node.kind() == SyntaxKind::SetOp)
             .map(SetOp)
     }
+
+    /// The range of the dice and their operations in the source text.
+    pub fn range(&self) -> TextRange {
+        trimmed_range(&self.0)
+    }
 }
 
 
@@ -148,6 +255,14 @@ impl ParenExpr {
 pub struct Set(SyntaxNode);
 
 impl Set {
+    pub fn cast(node: &SyntaxNode) -> Option<Self> {
+        if node.kind() == SyntaxKind::SetExpr {
+            Some(Self(node.clone()))
+        } else {
+            None
+        }
+    }
+
     pub fn items(&self) -> impl Iterator<Item=Expr> {
         self.0.children()
             .filter_map(Expr::cast)
@@ -161,10 +276,37 @@ impl Set {
 }
 
 
+#[derive(Debug)]
+pub struct TaggedExpr(SyntaxNode);
+
+impl TaggedExpr {
+    pub fn expr(&self) -> Option<Expr> {
+        self.0.children().find_map(Expr::cast)
+    }
+
+    /// The tag's name, without the surrounding brackets.
+    pub fn tag(&self) -> Option<String> {
+        self.0
+            .children_with_tokens()
+            .filter_map(SyntaxElement::into_token)
+            .find(|token| token.kind() == SyntaxKind::Tag)
+            .map(|token| token.text().trim_start_matches('[').trim_end_matches(']').to_string())
+    }
+}
+
+
 #[derive(Debug)]
 pub struct UnaryExpr(SyntaxNode);
 
 impl UnaryExpr {
+    pub fn cast(node: &SyntaxNode) -> Option<Self> {
+        if node.kind() == SyntaxKind::PrefixExpr {
+            Some(Self(node.clone()))
+        } else {
+            None
+        }
+    }
+
     pub fn expr(&self) -> Option<Expr> {
         self.0.children().find_map(Expr::cast)
     }
@@ -204,12 +346,17 @@ impl SetOp {
             .find(|token| SyntaxKind::SET_SELECTORS.contains(&token.kind()))
     }
 
+    /// The range of the operation in the source text, like `kh3`.
+    pub fn range(&self) -> TextRange {
+        trimmed_range(&self.0)
+    }
+
+    /// The number of the operation, or `None` if it is missing or too large.
     pub fn num(&self) -> Option<u64> {
         self.0
-            .last_child()
-            .unwrap()
-            .first_token()
-            .unwrap()
+            .children()
+            .find(|node| node.kind() == SyntaxKind::Literal)?
+            .first_token()?
             .text()
             .parse()
             .ok()
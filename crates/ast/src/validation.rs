@@ -1,20 +1,33 @@
 mod errors;
-use errors::{ValidationError, ValidationErrorKind};
+pub use errors::{ValidationError, ValidationErrorKind};
 
 
-use crate::{Dice, Literal};
-use syntax::SyntaxNode;
+use crate::{Dice, Expr, Literal, Set, SetOp, UnaryExpr};
+use syntax::{SyntaxKind, SyntaxNode};
 use text_size::{TextSize, TextRange};
 
 
+/// Set operations and the selectors they can't be used with: rerolling the highest or lowest dice
+/// would reroll forever, and minimums and maximums only take a number.
+const INCOMPATIBLE_SET_OPS: &[(SyntaxKind, &[SyntaxKind])] = &[
+    (SyntaxKind::Reroll, &[SyntaxKind::Highest, SyntaxKind::Lowest]),
+    (SyntaxKind::Min, &[SyntaxKind::Lowest, SyntaxKind::Highest, SyntaxKind::Greater, SyntaxKind::Less]),
+    (SyntaxKind::Max, &[SyntaxKind::Lowest, SyntaxKind::Highest, SyntaxKind::Greater, SyntaxKind::Less]),
+];
+
+
 pub fn validate(node: &SyntaxNode) -> Vec<ValidationError> {
     let mut errors = Vec::new();
 
     for node in node.descendants() {
         if let Some(dice) = Dice::cast(&node) {
             validate_dice(dice, &mut errors)
+        } else if let Some(set) = Set::cast(&node) {
+            validate_set(set, &mut errors)
         } else if let Some(literal) = Literal::cast(&node) {
             validate_literal(literal, &mut errors)
+        } else if let Some(unary) = UnaryExpr::cast(&node) {
+            validate_unary(unary, &mut errors)
         }
     }
 
@@ -23,17 +36,24 @@ pub fn validate(node: &SyntaxNode) -> Vec<ValidationError> {
 
 
 fn validate_dice(dice: Dice, errors: &mut Vec<ValidationError>) {
-    if dice.count().is_none() {
-        errors.push(ValidationError {
-            kind: ValidationErrorKind::NumberTooLarge,
-            range: {
-                let text = dice.0.first_token().unwrap();
-                let start = text.text_range().start();
-                let end = u32::from(start) + text.text().find('d').unwrap() as u32;
+    let count_range = || {
+        let text = dice.0.first_token().unwrap();
+        let start = text.text_range().start();
+        let end = u32::from(start) + text.text().find('d').unwrap() as u32;
 
-                TextRange::new(start, TextSize::from(end))
-            }
-        });
+        TextRange::new(start, TextSize::from(end))
+    };
+
+    match dice.count() {
+        None => errors.push(ValidationError {
+            kind: ValidationErrorKind::NumberTooLarge,
+            range: count_range(),
+        }),
+        Some(0) => errors.push(ValidationError {
+            kind: ValidationErrorKind::ZeroDiceCount,
+            range: count_range(),
+        }),
+        Some(_) => {}
     }
 
     if dice.sides().is_none() {
@@ -50,6 +70,154 @@ fn validate_dice(dice: Dice, errors: &mut Vec<ValidationError>) {
             }
         })
     }
+
+    let mut count = dice.count();
+
+    for op in dice.ops() {
+        validate_selector(&op, errors);
+        validate_pool_size(&op, count, errors);
+
+        if let Some(sides) = dice.sides() {
+            validate_selected_values(&op, sides, errors);
+        }
+
+        count = remaining(&op, count);
+    }
+}
+
+
+fn validate_set(set: Set, errors: &mut Vec<ValidationError>) {
+    let mut items = Some(set.items().count() as u64);
+
+    for op in set.ops() {
+        match op.op().map(|op| op.kind()) {
+            Some(SyntaxKind::Keep) | Some(SyntaxKind::Drop) => {
+                validate_pool_size(&op, items, errors);
+                items = remaining(&op, items);
+            }
+            _ => errors.push(ValidationError {
+                kind: ValidationErrorKind::UnsupportedSetOperation,
+                range: op.range(),
+            }),
+        }
+    }
+}
+
+
+fn validate_selector(op: &SetOp, errors: &mut Vec<ValidationError>) {
+    let (op, sel) = match (op.op(), op.sel()) {
+        (Some(op), Some(sel)) => (op, sel),
+        _ => return,
+    };
+
+    let incompatible = INCOMPATIBLE_SET_OPS
+        .iter()
+        .any(|(kind, sels)| op.kind() == *kind && sels.contains(&sel.kind()));
+
+    if incompatible {
+        errors.push(ValidationError {
+            kind: ValidationErrorKind::IncompatibleSelector,
+            range: TextRange::new(op.text_range().start(), sel.text_range().end()),
+        });
+    }
+}
+
+
+/// Warns about keeping more dice or items than there are, like `2d20kh3`, and dropping all of
+/// them, like `2d20pl2`.
+fn validate_pool_size(op: &SetOp, count: Option<u64>, errors: &mut Vec<ValidationError>) {
+    let selects_some = matches!(
+        op.sel().map(|sel| sel.kind()),
+        Some(SyntaxKind::Highest) | Some(SyntaxKind::Lowest),
+    );
+
+    let (count, num) = match (count, op.num()) {
+        (Some(count), Some(num)) if selects_some => (count, num),
+        _ => return,
+    };
+
+    let kind = match op.op().map(|op| op.kind()) {
+        Some(SyntaxKind::Keep) if num > count => ValidationErrorKind::KeepMoreThanRolled,
+        Some(SyntaxKind::Drop) if num >= count && count > 0 => ValidationErrorKind::DropsEverything,
+        _ => return,
+    };
+
+    errors.push(ValidationError { kind, range: op.range() });
+}
+
+/// How many of `count` dice or items still count after `op`, or `None` if there is no telling,
+/// like after an explosion adds dice or `k>3` keeps however many rolled over 3.
+fn remaining(op: &SetOp, count: Option<u64>) -> Option<u64> {
+    let count = count?;
+    let selects_some = matches!(
+        op.sel().map(|sel| sel.kind()),
+        Some(SyntaxKind::Highest) | Some(SyntaxKind::Lowest),
+    );
+
+    match (op.op().map(|op| op.kind()), op.num()) {
+        // Operations without a number are skipped when rolling.
+        (_, None) => Some(count),
+        (Some(SyntaxKind::Keep), Some(num)) if selects_some => Some(count.min(num)),
+        (Some(SyntaxKind::Drop), Some(num)) if selects_some => Some(count.saturating_sub(num)),
+        (Some(SyntaxKind::Keep), _)
+        | (Some(SyntaxKind::Drop), _)
+        | (Some(SyntaxKind::Explode), _)
+        | (Some(SyntaxKind::RerollAdd), _) => None,
+        _ => Some(count),
+    }
+}
+
+/// Warns about selectors that no roll of the dice can match, like `1d6rr7`, and rerolls and
+/// explosions that every roll matches, like `1d6e>0`, which only stop when they hit their limit.
+fn validate_selected_values(op: &SetOp, sides: u64, errors: &mut Vec<ValidationError>) {
+    let op_kind = match op.op() {
+        Some(op) => op.kind(),
+        None => return,
+    };
+    let num = match op.num() {
+        Some(num) => num,
+        None => return,
+    };
+
+    // Minimums and maximums are values to clamp to rather than selectors.
+    if matches!(op_kind, SyntaxKind::Min | SyntaxKind::Max) {
+        return;
+    }
+
+    let (matches_none, matches_all) = match op.sel().map(|sel| sel.kind()) {
+        None => (num == 0 || num > sides, sides == 1 && num == 1),
+        Some(SyntaxKind::Greater) => (num >= sides, num == 0),
+        Some(SyntaxKind::Less) => (num <= 1, num > sides),
+        _ => return,
+    };
+
+    let kind = if matches_none {
+        ValidationErrorKind::SelectsNothing
+    } else if matches_all && matches!(op_kind, SyntaxKind::Reroll | SyntaxKind::Explode) {
+        ValidationErrorKind::AlwaysRepeats
+    } else {
+        return;
+    };
+
+    errors.push(ValidationError { kind, range: op.range() });
+}
+
+
+/// Warns about `-2d6`, which reads like a negative dice count but negates a roll of `2d6`.
+fn validate_unary(unary: UnaryExpr, errors: &mut Vec<ValidationError>) {
+    let (op, dice) = match (unary.op(), unary.expr()) {
+        (Some(op), Some(Expr::Dice(dice))) => (op, dice),
+        _ => return,
+    };
+
+    let dice = dice.0.first_token().unwrap();
+
+    if op.text_range().end() == dice.text_range().start() {
+        errors.push(ValidationError {
+            kind: ValidationErrorKind::NegativeDiceCount,
+            range: TextRange::new(op.text_range().start(), dice.text_range().end()),
+        });
+    }
 }
 
 
@@ -113,11 +281,40 @@ mod tests {
         );
     }
 
+    #[test]
+    fn validate_zero_count_dice() {
+        check(
+            "0d6 + 1",
+            &[(ValidationErrorKind::ZeroDiceCount, (0..1))],
+        );
+    }
+
+    #[test]
+    fn validate_negative_count_dice() {
+        check(
+            "-2d6kh1",
+            &[(ValidationErrorKind::NegativeDiceCount, (0..4))],
+        );
+    }
+
+    #[test]
+    fn validate_negated_dice_with_whitespace() {
+        check("- 2d6", &[]);
+    }
+
+    #[test]
+    fn validate_subtracted_dice() {
+        check("1-2d6", &[]);
+    }
+
     #[test]
     fn validate_too_large_sides_dice() {
         check(
             "1d99999999999999999999kh2",
-            &[(ValidationErrorKind::NumberTooLarge, (2..22))],
+            &[
+                (ValidationErrorKind::NumberTooLarge, (2..22)),
+                (ValidationErrorKind::KeepMoreThanRolled, (22..25)),
+            ],
         );
     }
 
@@ -141,7 +338,82 @@ mod tests {
     fn validate_too_large_set_op_num() {
         check(
             "(1,2,3)e99999999999999999999",
-            &[(ValidationErrorKind::NumberTooLarge, (8..28))],
+            &[
+                (ValidationErrorKind::UnsupportedSetOperation, (7..28)),
+                (ValidationErrorKind::NumberTooLarge, (8..28)),
+            ],
         );
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn negative_count_diagnostic() {
+        let input = "1 + -2d6";
+        let errors = validate(&parser::parse(input).syntax());
+
+        assert_eq!(errors[0].diagnostic().render(input), "\
+warning[E0103]: dice counts cannot be negative, so this subtracts the roll instead
+  |
+1 | 1 + -2d6
+  |     ^ this negates the roll
+  |      --- of these dice
+  = help: put the dice in parentheses, like `-(2d6)`, to make it clear");
+    }
+
+    #[test]
+    fn validate_incompatible_selectors() {
+        check("4d6rrh1", &[(ValidationErrorKind::IncompatibleSelector, (3..6))]);
+        check("2d20mah1", &[(ValidationErrorKind::IncompatibleSelector, (4..7))]);
+        check("2d20mi>3", &[(ValidationErrorKind::IncompatibleSelector, (4..7))]);
+        check("4d6rr<3kh3", &[]);
+    }
+
+    #[test]
+    fn validate_keeping_more_than_rolled() {
+        check("2d20kh3", &[(ValidationErrorKind::KeepMoreThanRolled, (4..7))]);
+        check("(1d20, 5)kl3", &[(ValidationErrorKind::KeepMoreThanRolled, (9..12))]);
+        check("2d20kh2", &[]);
+        check("2d6e6kh3", &[]);
+    }
+
+    #[test]
+    fn validate_dropping_everything() {
+        check("2d20pl2", &[(ValidationErrorKind::DropsEverything, (4..7))]);
+        check("3d6ph2", &[]);
+    }
+
+    #[test]
+    fn validate_selectors_that_match_nothing() {
+        check("1d6rr7", &[(ValidationErrorKind::SelectsNothing, (3..6))]);
+        check("1d20cs>20", &[(ValidationErrorKind::SelectsNothing, (4..9))]);
+        check("4d6ro<1", &[(ValidationErrorKind::SelectsNothing, (3..7))]);
+        check("1d6mi7", &[]);
+    }
+
+    #[test]
+    fn validate_rerolls_that_never_stop() {
+        check("1d6e>0", &[(ValidationErrorKind::AlwaysRepeats, (3..6))]);
+        check("2d4rr<5", &[(ValidationErrorKind::AlwaysRepeats, (3..7))]);
+        check("2d4ro<5", &[]);
+    }
+
+    #[test]
+    fn validate_unsupported_set_operations() {
+        check("(1d6, 2)e6", &[(ValidationErrorKind::UnsupportedSetOperation, (8..10))]);
+    }
+
+    #[test]
+    fn validate_set_ops_without_a_number() {
+        check("4d6k+1", &[]);
+        check("4d6kh", &[]);
+    }
+
+    #[test]
+    fn validate_pool_size_after_keeping_and_dropping() {
+        check("4d6kh2kh3", &[(ValidationErrorKind::KeepMoreThanRolled, (6..9))]);
+        check("4d6pl1kh3", &[]);
+        check("4d6pl1ph3", &[(ValidationErrorKind::DropsEverything, (6..9))]);
+        check("4d6k>3kh3", &[]);
+        check("2d6e6kh3", &[]);
+        check("(1, 2, 3)kh2pl2", &[(ValidationErrorKind::DropsEverything, (12..15))]);
+    }
+}
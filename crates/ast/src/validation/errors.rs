@@ -1,16 +1,67 @@
+use diagnostic::{Diagnostic, Label};
 use std::fmt;
 use text_size::TextRange;
 
 
 #[derive(Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ValidationError {
     pub kind: ValidationErrorKind,
     pub range: TextRange,
 }
 
+impl ValidationError {
+    pub fn diagnostic(&self) -> Diagnostic {
+        use ValidationErrorKind::*;
+
+        let (label, help) = match self.kind {
+            NumberTooLarge => ("this number is too large", None),
+            ZeroDiceCount => {
+                ("this rolls no dice", Some("leave out the count to roll one die, like `d20`"))
+            }
+            NegativeDiceCount => {
+                // The range covers the minus sign and the dice after it.
+                let minus = TextRange::at(self.range.start(), 1.into());
+                let dice = TextRange::new(minus.end(), self.range.end());
+
+                return Diagnostic::warning(
+                    self.kind.code(),
+                    self.kind.to_string(),
+                    Label::new(minus, "this negates the roll"),
+                )
+                .with_secondary(Label::new(dice, "of these dice"))
+                .with_help("put the dice in parentheses, like `-(2d6)`, to make it clear");
+            }
+            IncompatibleSelector => {
+                ("this selector isn't allowed here", Some("use a plain number, like `rr1` or `mi2`"))
+            }
+            KeepMoreThanRolled => ("this keeps everything", None),
+            DropsEverything => ("this drops everything", None),
+            SelectsNothing => ("this never matches", None),
+            AlwaysRepeats => ("this always matches", None),
+            UnsupportedSetOperation => {
+                ("this is ignored", Some("apply it to the dice inside the set instead"))
+            }
+        };
+
+        let label = Label::new(self.range, label);
+        let diagnostic = if self.kind.is_warning() {
+            Diagnostic::warning(self.kind.code(), self.kind.to_string(), label)
+        } else {
+            Diagnostic::error(self.kind.code(), self.kind.to_string(), label)
+        };
+
+        match help {
+            Some(help) => diagnostic.with_help(help),
+            None => diagnostic,
+        }
+    }
+}
+
 impl fmt::Display for ValidationError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "error at {}..{}: {}",
+        write!(f, "{} at {}..{}: {}",
+               if self.kind.is_warning() { "warning" } else { "error" },
                u32::from(self.range.start()),
                u32::from(self.range.end()),
                self.kind,
@@ -20,8 +71,55 @@ impl fmt::Display for ValidationError {
 
 
 #[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
 pub enum ValidationErrorKind {
     NumberTooLarge,
+    ZeroDiceCount,
+    NegativeDiceCount,
+    /// A set operation with a selector it can't use, like `rrh1` or `mah1`.
+    IncompatibleSelector,
+    /// Keeping the highest or lowest of more dice or items than there are, like `2d20kh3`.
+    KeepMoreThanRolled,
+    /// Dropping the highest or lowest of all the dice or items, like `2d20pl2`.
+    DropsEverything,
+    /// A selector that no roll of the dice matches, like `1d6rr7`.
+    SelectsNothing,
+    /// A reroll or explosion that every roll of the dice matches, like `1d6e>0`.
+    AlwaysRepeats,
+    /// An operation other than keeping or dropping applied to a set, which does nothing.
+    UnsupportedSetOperation,
+}
+
+impl ValidationErrorKind {
+    /// The code of the kind of error in its diagnostic, which never changes.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::NumberTooLarge => "E0101",
+            Self::ZeroDiceCount => "E0102",
+            Self::NegativeDiceCount => "E0103",
+            Self::IncompatibleSelector => "E0104",
+            Self::KeepMoreThanRolled => "E0105",
+            Self::DropsEverything => "E0106",
+            Self::SelectsNothing => "E0107",
+            Self::AlwaysRepeats => "E0108",
+            Self::UnsupportedSetOperation => "E0109",
+        }
+    }
+
+    /// Warnings point out input that is valid but probably not what was meant.
+    pub fn is_warning(&self) -> bool {
+        matches!(
+            self,
+            Self::ZeroDiceCount
+                | Self::NegativeDiceCount
+                | Self::KeepMoreThanRolled
+                | Self::DropsEverything
+                | Self::SelectsNothing
+                | Self::AlwaysRepeats
+                | Self::UnsupportedSetOperation
+        )
+    }
 }
 
 impl fmt::Display for ValidationErrorKind {
@@ -29,6 +127,22 @@ impl fmt::Display for ValidationErrorKind {
         match self {
             Self::NumberTooLarge => write!(f,
                                            "number is larger than an integer's maximum value, {}", u64::MAX),
+            Self::ZeroDiceCount => write!(f,
+                                          "rolling zero dice totals 0 unless a zero dice policy is set"),
+            Self::NegativeDiceCount => write!(f,
+                                              "dice counts cannot be negative, so this subtracts the roll instead"),
+            Self::IncompatibleSelector => write!(f,
+                                                 "'rr' can't pick the highest or lowest dice, and 'mi' and 'ma' only take a number"),
+            Self::KeepMoreThanRolled => write!(f,
+                                               "this keeps more than there are, so nothing is dropped"),
+            Self::DropsEverything => write!(f,
+                                            "this drops everything, so it totals 0"),
+            Self::SelectsNothing => write!(f,
+                                           "no roll of these dice matches this, so it does nothing"),
+            Self::AlwaysRepeats => write!(f,
+                                          "every roll of these dice matches this, so it only stops at the limit of repeats"),
+            Self::UnsupportedSetOperation => write!(f,
+                                                    "only 'k' and 'p' apply to sets, so this does nothing"),
         }
     }
 }
\ No newline at end of file
@@ -0,0 +1,240 @@
+use std::fmt;
+use text_size::TextRange;
+
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    /// Input that is valid but probably not what was meant.
+    Warning,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Error => "error",
+            Self::Warning => "warning",
+        })
+    }
+}
+
+
+/// A range of the source text, and what to say about it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Label {
+    pub range: TextRange,
+    pub message: String,
+}
+
+impl Label {
+    pub fn new(range: TextRange, message: impl Into<String>) -> Self {
+        Self { range, message: message.into() }
+    }
+}
+
+
+/// A problem with some input, found while parsing or validating it.
+///
+/// Its `Display` implementation is a one-line summary, like
+/// `error[E0001] at 5..6: expected number, but found '+'`; `render` shows it under the input.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    /// A code that identifies the kind of problem, like `E0001`. Codes never change meaning, so
+    /// they can be looked up or matched on even when the wording of messages changes.
+    pub code: &'static str,
+    pub message: String,
+    /// Where the problem is, underlined with `^`.
+    pub primary: Label,
+    /// Other places that explain the problem, underlined with `-`.
+    pub secondary: Vec<Label>,
+    pub help: Option<String>,
+}
+
+impl Diagnostic {
+    pub fn error(code: &'static str, message: impl Into<String>, primary: Label) -> Self {
+        Self::new(Severity::Error, code, message.into(), primary)
+    }
+
+    pub fn warning(code: &'static str, message: impl Into<String>, primary: Label) -> Self {
+        Self::new(Severity::Warning, code, message.into(), primary)
+    }
+
+    fn new(severity: Severity, code: &'static str, message: String, primary: Label) -> Self {
+        Self { severity, code, message, primary, secondary: Vec::new(), help: None }
+    }
+
+    pub fn with_secondary(mut self, label: Label) -> Self {
+        self.secondary.push(label);
+        self
+    }
+
+    pub fn with_help(mut self, help: impl Into<String>) -> Self {
+        self.help = Some(help.into());
+        self
+    }
+
+    /// Shows the diagnostic under the lines of `source` that it is about:
+    ///
+    /// ```text
+    /// error[E0001]: expected number, dice, '-', or '(', but found ')'
+    ///   |
+    /// 1 | (1d20 + )
+    ///   |        ^ expected an operand
+    /// ```
+    ///
+    /// `source` must be the text that the diagnostic's ranges are offsets into.
+    pub fn render(&self, source: &str) -> String {
+        let labels: Vec<_> = std::iter::once((&self.primary, '^'))
+            .chain(self.secondary.iter().map(|label| (label, '-')))
+            .map(|(label, marker)| (Span::new(source, label.range), label, marker))
+            .collect();
+
+        let mut line_numbers: Vec<_> = labels.iter().map(|(span, ..)| span.line).collect();
+        line_numbers.sort_unstable();
+        line_numbers.dedup();
+
+        let width = line_numbers.last().map_or(1, |line| (line + 1).to_string().len());
+        let gutter = " ".repeat(width);
+
+        let mut out = vec![
+            format!("{}[{}]: {}", self.severity, self.code, self.message),
+            format!("{} |", gutter),
+        ];
+
+        for line in line_numbers {
+            let text = source.split('\n').nth(line).unwrap_or_default().trim_end();
+            out.push(format!("{:>width$} | {}", line + 1, text, width = width));
+
+            for (span, label, marker) in labels.iter().filter(|(span, ..)| span.line == line) {
+                let underline = format!(
+                    "{}{} {}",
+                    " ".repeat(span.column),
+                    marker.to_string().repeat(span.width),
+                    label.message,
+                );
+                out.push(format!("{} | {}", gutter, underline.trim_end()));
+            }
+        }
+
+        if let Some(help) = &self.help {
+            out.push(format!("{} = help: {}", gutter, help));
+        }
+
+        out.join("\n")
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}[{}] at {}..{}: {}",
+               self.severity,
+               self.code,
+               u32::from(self.primary.range.start()),
+               u32::from(self.primary.range.end()),
+               self.message,
+        )
+    }
+}
+
+
+/// Where a range is on screen: its line, the column it starts at, and how many columns to
+/// underline. A range that runs over several lines is only underlined on its first.
+struct Span {
+    line: usize,
+    column: usize,
+    width: usize,
+}
+
+impl Span {
+    fn new(source: &str, range: TextRange) -> Self {
+        let start = usize::from(range.start()).min(source.len());
+        let end = usize::from(range.end()).min(source.len());
+
+        let line_start = source[..start].rfind('\n').map_or(0, |newline| newline + 1);
+        let line_end = source[start..].find('\n').map_or(source.len(), |newline| start + newline);
+
+        Self {
+            line: source[..start].matches('\n').count(),
+            column: source[line_start..start].chars().count(),
+            width: source[start..end.min(line_end)].chars().count().max(1),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn range(start: u32, end: u32) -> TextRange {
+        TextRange::new(start.into(), end.into())
+    }
+
+    #[test]
+    fn displays_a_summary() {
+        let diagnostic = Diagnostic::warning("E0202", "zero dice", Label::new(range(0, 1), ""));
+
+        assert_eq!(diagnostic.to_string(), "warning[E0202] at 0..1: zero dice");
+    }
+
+    #[test]
+    fn renders_the_line_with_carets() {
+        let diagnostic = Diagnostic::error(
+            "E0001",
+            "expected number, but found '+'",
+            Label::new(range(7, 8), "expected an operand"),
+        );
+
+        assert_eq!(diagnostic.render("1d20 + + 2"), "\
+error[E0001]: expected number, but found '+'
+  |
+1 | 1d20 + + 2
+  |        ^ expected an operand");
+    }
+
+    #[test]
+    fn renders_secondary_labels_and_help() {
+        let diagnostic = Diagnostic::warning(
+            "E0203",
+            "dice counts cannot be negative",
+            Label::new(range(4, 5), "this subtracts the roll"),
+        )
+        .with_secondary(Label::new(range(5, 8), "from nothing"))
+        .with_help("write `0 - 2d6` to make that clear");
+
+        assert_eq!(diagnostic.render("1 + -2d6"), "\
+warning[E0203]: dice counts cannot be negative
+  |
+1 | 1 + -2d6
+  |     ^ this subtracts the roll
+  |      --- from nothing
+  = help: write `0 - 2d6` to make that clear");
+    }
+
+    #[test]
+    fn renders_only_the_lines_with_labels() {
+        let source = "1d20\n+ 3\n+ 99999999999999999999\n";
+        let diagnostic = Diagnostic::error("E0201", "number is too large", Label::new(range(11, 40), ""))
+            .with_secondary(Label::new(range(0, 4), "in this roll"));
+
+        assert_eq!(diagnostic.render(source), "\
+error[E0201]: number is too large
+  |
+1 | 1d20
+  | ---- in this roll
+3 | + 99999999999999999999
+  |   ^^^^^^^^^^^^^^^^^^^^");
+    }
+
+    #[test]
+    fn underlines_the_end_of_the_input() {
+        let diagnostic = Diagnostic::error("E0002", "expected ')'", Label::new(range(3, 3), "here"));
+
+        assert_eq!(diagnostic.render("(1d"), "\
+error[E0002]: expected ')'
+  |
+1 | (1d
+  |    ^ here");
+    }
+}
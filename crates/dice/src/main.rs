@@ -1,38 +1,212 @@
 use parser::parse;
-use std::io::{self, Write};
+use std::io::{self, IsTerminal, Write};
+use std::process;
+
+
+/// Asks for the value of a physical die on stdin, like `d20 #1?`. Exits if stdin is closed.
+fn ask_for_die(request: hir::DieRequest) -> u64 {
+    if let Some(value) = request.rejected {
+        println!("A d{} can't roll {}.", request.sides, value);
+    }
+
+    let mut input = String::new();
+
+    loop {
+        print!("d{} #{}? ", request.sides, request.position);
+        io::stdout().flush().unwrap();
+
+        input.clear();
+        if io::stdin().read_line(&mut input).unwrap() == 0 {
+            process::exit(0);
+        }
+
+        match input.trim().parse() {
+            Ok(value) => return value,
+            Err(_) => println!("{:?} isn't a number.", input.trim()),
+        }
+    }
+}
+
+
+/// Prints every parse and validation error in `input`, shown under the part of it they are about.
+fn print_diagnostics(input: &str, parse: &parser::Parse, syntax: &syntax::SyntaxNode) {
+    let validation = ast::validation::validate(syntax)
+        .iter()
+        .map(|error| error.diagnostic())
+        .collect();
+
+    for diagnostic in [parse.diagnostics(), validation].concat() {
+        println!("{}\n", diagnostic.render(input));
+    }
+}
+
+
+/// The chances of each expression rolling higher than the other, as in
+/// `dice compare "2d6" "1d12"`. Fails if either expression can't be simulated, like when it
+/// could divide by zero.
+fn compare(lhs: &str, rhs: &str) -> Result<String, hir::SimulationError> {
+    let compile = |input: &str| {
+        let parse = parse(input);
+        let syntax = parse.syntax();
+
+        print_diagnostics(input, &parse, &syntax);
+
+        hir::Program::compile(ast::Root::cast(syntax).unwrap())
+    };
+
+    let (comparison, exact) = hir::compare(&compile(lhs), &compile(rhs))?;
+
+    let mut out = format!(
+        "P({lhs} > {rhs}) = {:.4}\nP({lhs} = {rhs}) = {:.4}\nP({lhs} < {rhs}) = {:.4}\n",
+        comparison.greater,
+        comparison.equal,
+        comparison.less,
+        lhs = lhs,
+        rhs = rhs,
+    );
+
+    if !exact {
+        out.push_str("These probabilities were estimated by simulation.\n");
+    }
+
+    Ok(out)
+}
+
+
+/// Prints each line of stdin formatted, as in `dice fmt < macros.txt`.
+fn format_lines() -> io::Result<()> {
+    for line in io::stdin().lines() {
+        let root = ast::Root::cast(parse(&line?).syntax()).unwrap();
+        println!("{}", ast::format::format(&root));
+    }
+
+    Ok(())
+}
 
 
 fn main() -> io::Result<()> {
+    let args: Vec<_> = std::env::args().skip(1).collect();
+
+    if let [command] = args.as_slice() {
+        if command == "fmt" {
+            return format_lines();
+        }
+    }
+
+    if let [command, lhs, rhs] = args.as_slice() {
+        if command == "compare" {
+            match compare(lhs, rhs) {
+                Ok(comparison) => print!("{}", comparison),
+                Err(error) => {
+                    eprintln!("error: {}", error);
+                    process::exit(1);
+                }
+            }
+
+            return Ok(());
+        }
+    }
+
     let stdin = io::stdin();
     let mut stdout = io::stdout();
 
+    // With `--manual`, every die is rolled by hand and its value typed in.
+    let manual = args.iter().any(|arg| arg == "--manual");
+
     let mut input = String::new();
 
     loop {
         write!(stdout, "> ")?;
         stdout.flush()?;
 
-        stdin.read_line(&mut input)?;
+        if stdin.read_line(&mut input)? == 0 {
+            return Ok(());
+        }
 
         let parse = parse(&input);
         println!("{}", parse.debug_tree());
 
         let syntax = parse.syntax();
 
-        for error in ast::validation::validate(&syntax) {
-            println!("{}", error);
-        }
+        print_diagnostics(&input, &parse, &syntax);
 
         let root = ast::Root::cast(syntax).unwrap();
 
         dbg!(root.expr());
 
-        let mut roll_result = hir::roll(root);
-        let total = roll_result.total();
-        dbg!(roll_result);
+        let roll_result = if manual {
+            hir::roll_with(root, hir::RollContext::new(hir::ManualRolls::new(ask_for_die)))
+        } else {
+            hir::roll(root)
+        };
+        let tagged_total = roll_result.tagged_total();
+        let (is_crit, is_fumble) = (roll_result.is_crit(), roll_result.is_fumble());
+        let versus = roll_result.versus(hir::TiePolicy::default());
+        let band = roll_result.band();
+
+        let breakdown = roll_result.breakdown();
+
+        if io::stdout().is_terminal() {
+            println!("{}", breakdown.render(&mut hir::Ansi::default()));
+        } else {
+            println!("{}", breakdown.render(&mut hir::Plain));
+        }
+
+        match (tagged_total, band) {
+            (Ok(tagged_total), Ok(band)) => {
+                match band {
+                    Some(band) => println!("Total: {} ({})", tagged_total.total, band),
+                    None => println!("Total: {}", tagged_total.total),
+                }
+
+                for (tag, total) in tagged_total.tags {
+                    println!("  {}: {}", tag, total);
+                }
+            }
+            (Err(error), _) | (_, Err(error)) => println!("No total: {}", error),
+        }
+
+        if let Ok(Some(versus)) = versus {
+            println!("{} vs {}: {:?} by {}", versus.lhs, versus.rhs, versus.winner, versus.margin);
+        }
 
-        println!("Total: {}", total);
+        if is_crit {
+            println!("Critical success!");
+        }
+        if is_fumble {
+            println!("Critical failure!");
+        }
 
         input.clear();
     }
-}
\ No newline at end of file
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compare_exactly() {
+        assert_eq!(compare("1d20", "1d20").unwrap(), "\
+P(1d20 > 1d20) = 0.4750
+P(1d20 = 1d20) = 0.0500
+P(1d20 < 1d20) = 0.4750
+");
+    }
+
+    #[test]
+    fn compare_wide_and_narrow_distributions() {
+        assert_eq!(compare("1d1000000", "1d6").unwrap(), "\
+P(1d1000000 > 1d6) = 1.0000
+P(1d1000000 = 1d6) = 0.0000
+P(1d1000000 < 1d6) = 0.0000
+These probabilities were estimated by simulation.
+");
+    }
+
+    #[test]
+    fn compare_fails_if_a_roll_does() {
+        assert!(compare("1d6 / (1d2 - 1)", "3").is_err());
+    }
+}
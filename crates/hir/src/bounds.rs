@@ -0,0 +1,503 @@
+use crate::{BinaryOp, Node, NodeIdx, Program, SetOp, SetOperation, SetSel, UnaryOp};
+use std::cmp::{max, min};
+use std::fmt;
+
+
+/// One end of the range of totals an expression can roll.
+///
+/// Bounds are worked out in `i128`, so that ranges too wide for an `i64` can still be reported.
+/// A bound too large even for that is unbounded.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Bound {
+    NegInfinity,
+    Finite(i128),
+    Infinity,
+}
+
+impl Bound {
+    fn saturate(n: Option<i128>, positive: bool) -> Self {
+        match (n, positive) {
+            (Some(n), _) => Self::Finite(n),
+            (None, true) => Self::Infinity,
+            (None, false) => Self::NegInfinity,
+        }
+    }
+
+    fn signum(self) -> i128 {
+        match self {
+            Self::NegInfinity => -1,
+            Self::Finite(n) => n.signum(),
+            Self::Infinity => 1,
+        }
+    }
+
+    fn neg(self) -> Self {
+        match self {
+            Self::NegInfinity => Self::Infinity,
+            Self::Finite(n) => Self::saturate(n.checked_neg(), true),
+            Self::Infinity => Self::NegInfinity,
+        }
+    }
+
+    /// Adds two lower bounds or two upper bounds, which are never infinite in opposite
+    /// directions.
+    fn add(self, other: Self) -> Self {
+        match (self, other) {
+            (Self::Finite(a), Self::Finite(b)) => Self::saturate(a.checked_add(b), a > 0),
+            (Self::Finite(_), infinite) | (infinite, _) => infinite,
+        }
+    }
+
+    fn mul(self, other: Self) -> Self {
+        match (self, other) {
+            (Self::Finite(a), Self::Finite(b)) => {
+                Self::saturate(a.checked_mul(b), a.signum() * b.signum() > 0)
+            }
+            _ => match self.signum() * other.signum() {
+                0 => Self::Finite(0),
+                1 => Self::Infinity,
+                _ => Self::NegInfinity,
+            },
+        }
+    }
+
+    /// Divides, truncating towards zero, by a nonzero divisor. Dividing by an infinite divisor
+    /// gives the limit of dividing by ever larger numbers, which is 0.
+    fn div(self, other: Self) -> Self {
+        match (self, other) {
+            (Self::Finite(a), Self::Finite(b)) => Self::saturate(a.checked_div(b), true),
+            (_, Self::NegInfinity | Self::Infinity) => Self::Finite(0),
+            (infinite, Self::Finite(b)) => if b > 0 { infinite } else { infinite.neg() },
+        }
+    }
+}
+
+impl fmt::Display for Bound {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Finite(n) => write!(f, "{}", n),
+            Self::NegInfinity | Self::Infinity => write!(f, "unbounded"),
+        }
+    }
+}
+
+
+/// The lowest and highest totals an expression can roll, worked out without rolling it.
+///
+/// Rerolls and explosions are taken to go on for as long as they match, without the cap that
+/// keeps rolling from running forever, so an explosion that can keep going is unbounded.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Bounds {
+    pub min: Bound,
+    pub max: Bound,
+}
+
+impl Bounds {
+    pub fn of(program: &Program) -> Self {
+        bounds(program, program.root())
+    }
+
+    /// Whether every total the expression can roll fits in an `i64`.
+    pub fn fits_in_i64(&self) -> bool {
+        let range = Bound::Finite(i64::MIN as i128)..=Bound::Finite(i64::MAX as i128);
+
+        range.contains(&self.min) && range.contains(&self.max)
+    }
+
+    fn exactly(n: i128) -> Self {
+        Self { min: Bound::Finite(n), max: Bound::Finite(n) }
+    }
+
+    fn unbounded() -> Self {
+        Self { min: Bound::NegInfinity, max: Bound::Infinity }
+    }
+
+    fn add(self, other: Self) -> Self {
+        Self { min: self.min.add(other.min), max: self.max.add(other.max) }
+    }
+
+    fn neg(self) -> Self {
+        Self { min: self.max.neg(), max: self.min.neg() }
+    }
+
+    fn sub(self, other: Self) -> Self {
+        self.add(other.neg())
+    }
+
+    /// Widens the bounds to include 0, for something that might not count towards a total.
+    fn or_zero(self) -> Self {
+        Self {
+            min: min(self.min, Bound::Finite(0)),
+            max: max(self.max, Bound::Finite(0)),
+        }
+    }
+
+    fn hull(candidates: impl IntoIterator<Item=Bound>) -> Self {
+        let candidates: Vec<_> = candidates.into_iter().collect();
+
+        Self {
+            min: *candidates.iter().min().unwrap(),
+            max: *candidates.iter().max().unwrap(),
+        }
+    }
+
+    fn mul(self, other: Self) -> Self {
+        Self::hull([
+            self.min.mul(other.min),
+            self.min.mul(other.max),
+            self.max.mul(other.min),
+            self.max.mul(other.max),
+        ])
+    }
+
+    /// Divides by every divisor but 0, which can't be divided by. A divisor that can only be 0
+    /// leaves the expression unbounded.
+    fn div(self, other: Self) -> Self {
+        let one = Bound::Finite(1);
+        let negative = (other.min < Bound::Finite(0)).then(|| (other.min, min(other.max, one.neg())));
+        let positive = (other.max > Bound::Finite(0)).then(|| (max(other.min, one), other.max));
+
+        let candidates: Vec<_> = negative.into_iter()
+            .chain(positive)
+            .flat_map(|(lo, hi)| [
+                self.min.div(lo), self.min.div(hi), self.max.div(lo), self.max.div(hi),
+            ])
+            .collect();
+
+        if candidates.is_empty() {
+            Self::unbounded()
+        } else {
+            Self::hull(candidates)
+        }
+    }
+}
+
+impl fmt::Display for Bounds {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} to {}", self.min, self.max)
+    }
+}
+
+
+fn bounds(program: &Program, idx: NodeIdx) -> Bounds {
+    match &program[idx] {
+        Node::Missing | Node::Literal(None) => Bounds::exactly(0),
+        Node::Literal(Some(n)) => Bounds::exactly(*n as i128),
+        Node::Band { expr, .. } | Node::Tagged { expr, .. } => bounds(program, *expr),
+        Node::Binary { op, lhs, rhs } => {
+            let (lhs, rhs) = (bounds(program, *lhs), bounds(program, *rhs));
+
+            match op {
+                BinaryOp::Add => lhs.add(rhs),
+                BinaryOp::Sub => lhs.sub(rhs),
+                BinaryOp::Mul => lhs.mul(rhs),
+                BinaryOp::Div => lhs.div(rhs),
+            }
+        }
+        Node::Dice { count: Some(count), sides: Some(sides), ops, .. } if *count > 0 => {
+            Pool::new(*count as i128, *sides as i128, ops).total()
+        }
+        Node::Dice { .. } => Bounds::exactly(0),
+        Node::Set { items, ops } => {
+            let items = items.iter().map(|item| bounds(program, *item)).collect();
+
+            set_bounds(items, ops)
+        }
+        Node::Unary { op: UnaryOp::Neg, expr } => bounds(program, *expr).neg(),
+        Node::Versus { lhs, rhs } => bounds(program, *lhs).sub(bounds(program, *rhs)),
+    }
+}
+
+fn set_bounds(items: Vec<Bounds>, ops: &[SetOperation]) -> Bounds {
+    // Keep and Drop are the only operations that apply to sets; any others are ignored.
+    let ops: Vec<_> = ops.iter()
+        .filter(|op| op.num.is_some() && matches!(op.op, SetOp::Keep | SetOp::Drop))
+        .collect();
+
+    let sum = |items: &mut dyn Iterator<Item=Bounds>| {
+        items.fold(Bounds::exactly(0), Bounds::add)
+    };
+
+    match ops.as_slice() {
+        [] => sum(&mut items.into_iter()),
+        [op] if matches!(op.sel, SetSel::Highest | SetSel::Lowest) => {
+            // The kept items total at least the items with the highest (or lowest) minimums,
+            // and at most those with the highest (or lowest) maximums.
+            let selected = min(op.num.unwrap() as usize, items.len());
+            let kept = if op.op == SetOp::Keep { selected } else { items.len() - selected };
+            let highest = (op.op == SetOp::Keep) == (op.sel == SetSel::Highest);
+
+            let mut mins: Vec<_> = items.iter().map(|item| item.min).collect();
+            let mut maxes: Vec<_> = items.iter().map(|item| item.max).collect();
+            mins.sort();
+            maxes.sort();
+
+            if highest {
+                mins.reverse();
+                maxes.reverse();
+            }
+
+            let total = |ends: &[Bound]| {
+                ends.iter().take(kept).fold(Bound::Finite(0), |sum, end| sum.add(*end))
+            };
+
+            Bounds { min: total(&mins), max: total(&maxes) }
+        }
+        _ => sum(&mut items.into_iter().map(Bounds::or_zero)),
+    }
+}
+
+
+/// The smallest range holding every value in `range` that `op` selects, or that it doesn't if
+/// `selected` is false. `h` and `l` might select any value.
+fn selected_range(op: &SetOperation, (lo, hi): (i128, i128), selected: bool) -> Option<(i128, i128)> {
+    let num = op.num.unwrap() as i128;
+    let range = |lo, hi| (lo <= hi).then_some((lo, hi));
+
+    match (op.sel, selected) {
+        (SetSel::Highest | SetSel::Lowest, _) => Some((lo, hi)),
+        (SetSel::Greater, true) => range(max(lo, num + 1), hi),
+        (SetSel::Greater, false) => range(lo, min(hi, num)),
+        (SetSel::Less, true) => range(lo, min(hi, num - 1)),
+        (SetSel::Less, false) => range(max(lo, num), hi),
+        (SetSel::Number, true) => range(max(lo, num), min(hi, num)),
+        (SetSel::Number, false) if lo == num => range(lo + 1, hi),
+        (SetSel::Number, false) if hi == num => range(lo, hi - 1),
+        (SetSel::Number, false) => Some((lo, hi)),
+    }
+}
+
+fn hull(a: Option<(i128, i128)>, b: Option<(i128, i128)>) -> Option<(i128, i128)> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some((min(a.0, b.0), max(a.1, b.1))),
+        (a, None) => a,
+        (None, b) => b,
+    }
+}
+
+
+/// What is known about a pool of dice as its operations are applied. Every die's value is
+/// between 0 and the largest face or clamp, so a pool's total is bounded by how many of its dice
+/// count and the values those dice can show.
+struct Pool {
+    sides: i128,
+    /// How many dice there can be, which only explosions change.
+    dice: (i128, Bound),
+    /// The values any die can show.
+    values: (i128, i128),
+    /// How many dice can count towards the total.
+    counted: (i128, Bound),
+    /// The values a die that counts towards the total can show, or `None` if none can count.
+    counted_values: Option<(i128, i128)>,
+}
+
+impl Pool {
+    fn new(count: i128, sides: i128, ops: &[SetOperation]) -> Self {
+        let mut pool = Self {
+            sides,
+            dice: (count, Bound::Finite(count)),
+            values: (1, sides),
+            counted: (count, Bound::Finite(count)),
+            counted_values: Some((1, sides)),
+        };
+
+        // Operations with a number too large to parse have already been reported by validation.
+        for op in ops.iter().filter(|op| op.num.is_some()) {
+            pool.apply(op);
+        }
+
+        pool
+    }
+
+    fn total(&self) -> Bounds {
+        match self.counted_values {
+            Some((lo, hi)) => Bounds {
+                min: Bound::Finite(self.counted.0).mul(Bound::Finite(lo)),
+                max: self.counted.1.mul(Bound::Finite(hi)),
+            },
+            None => Bounds::exactly(0),
+        }
+    }
+
+    fn apply(&mut self, op: &SetOperation) {
+        let num = op.num.unwrap() as i128;
+
+        match (op.op, op.sel) {
+            (SetOp::Min, _) => self.map_values(|(lo, hi)| (max(lo, num), max(hi, num))),
+            (SetOp::Max, _) => self.map_values(|(lo, hi)| (min(lo, num), min(hi, num))),
+            (SetOp::Keep | SetOp::Drop, SetSel::Highest | SetSel::Lowest) => {
+                let (dice, (counted_min, counted_max)) = (self.dice.1, self.counted);
+                let num_value = num;
+                let num = Bound::Finite(num);
+
+                // The dice that are left unselected, which can't be more than are rolled.
+                let unselected = |dice: Bound| max(dice.add(num.neg()), Bound::Finite(0));
+                let at_least = |n: Bound| match n {
+                    Bound::Finite(n) => max(n, 0),
+                    _ => 0,
+                };
+
+                self.counted = if op.op == SetOp::Keep && self.counted == self.dice {
+                    // Every die still counts, so as many are kept as there are to keep.
+                    (min(self.dice.0, num_value), min(counted_max, num))
+                } else if op.op == SetOp::Keep {
+                    (at_least(Bound::Finite(counted_min).add(unselected(dice).neg())),
+                     min(counted_max, num))
+                } else {
+                    (at_least(Bound::Finite(counted_min).add(num.neg())),
+                     min(counted_max, unselected(dice)))
+                };
+            }
+            (SetOp::Keep | SetOp::Drop, _) => {
+                let keep = op.op == SetOp::Keep;
+
+                if let Some(range) = self.counted_values {
+                    if selected_range(op, range, !keep).is_some() {
+                        self.counted.0 = 0;
+                    }
+
+                    self.counted_values = selected_range(op, range, keep);
+                }
+            }
+            (SetOp::Reroll | SetOp::RerollOnce, _) => {
+                let faces = (1, self.sides);
+                let rerolled = if op.op == SetOp::Reroll && op.sel != SetSel::Highest && op.sel != SetSel::Lowest {
+                    selected_range(op, faces, false).unwrap_or(faces)
+                } else {
+                    faces
+                };
+
+                self.map_values(|range| match selected_range(op, range, true) {
+                    Some(_) => hull(selected_range(op, range, false), Some(rerolled)).unwrap(),
+                    None => range,
+                });
+            }
+            (SetOp::Explode | SetOp::RerollAdd, _) => self.explode(op),
+            (SetOp::CritSuccess | SetOp::CritFail, _) => {}
+        }
+    }
+
+    fn explode(&mut self, op: &SetOperation) {
+        let faces = (1, self.sides);
+
+        if selected_range(op, self.values, true).is_none() {
+            return;
+        }
+
+        let repeats = op.op == SetOp::Explode && selected_range(op, faces, true).is_some();
+        let extra = if repeats { Bound::Infinity } else { self.dice.1 };
+
+        // When every die explodes, each adds at least one more die.
+        let certain = !matches!(op.sel, SetSel::Highest | SetSel::Lowest)
+            && selected_range(op, self.values, false).is_none();
+        let at_least = if certain { self.dice.0 } else { 0 };
+
+        self.dice = (self.dice.0 + at_least, self.dice.1.add(extra));
+        self.counted = (self.counted.0 + at_least, self.counted.1.add(extra));
+        self.values = hull(Some(self.values), Some(faces)).unwrap();
+        self.counted_values = hull(self.counted_values, Some(faces));
+    }
+
+    fn map_values(&mut self, f: impl Fn((i128, i128)) -> (i128, i128)) {
+        self.values = f(self.values);
+        self.counted_values = self.counted_values.map(f);
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check(input: &str, min: Bound, max: Bound) {
+        let program = Program::compile(ast::Root::cast(parser::parse(input).syntax()).unwrap());
+
+        assert_eq!(Bounds::of(&program), Bounds { min, max }, "{}", input);
+    }
+
+    fn check_finite(input: &str, min: i128, max: i128) {
+        check(input, Bound::Finite(min), Bound::Finite(max));
+    }
+
+    #[test]
+    fn literals_and_missing() {
+        check_finite("7", 7, 7);
+        check_finite("1 +", 1, 1);
+        check_finite("0d6", 0, 0);
+    }
+
+    #[test]
+    fn dice() {
+        check_finite("3d6", 3, 18);
+        check_finite("d%", 1, 100);
+    }
+
+    #[test]
+    fn arithmetic() {
+        check_finite("2 * 1d6 - 1", 1, 11);
+        check_finite("-1d6", -6, -1);
+        check_finite("1d6 * -1d4", -24, -1);
+        check_finite("1d6 vs 1d4", -3, 5);
+    }
+
+    #[test]
+    fn division() {
+        check_finite("1d20 / 2", 0, 10);
+        check_finite("10 / 1d4", 2, 10);
+        check_finite("1d6 / (1d3 - 2)", -6, 6);
+        check("1d6 / 0", Bound::NegInfinity, Bound::Infinity);
+    }
+
+    #[test]
+    fn keep_and_drop() {
+        check_finite("4d6kh3", 3, 18);
+        check_finite("4d6pl1", 3, 18);
+        check_finite("4d6kl5", 4, 24);
+        check_finite("4d6k>3", 0, 24);
+        check_finite("4d6p6", 0, 20);
+        check_finite("4d6k>3kh2", 0, 12);
+    }
+
+    #[test]
+    fn clamps() {
+        check_finite("1d6mi3", 3, 6);
+        check_finite("1d6ma2", 1, 2);
+        check_finite("2d6mi8", 16, 16);
+    }
+
+    #[test]
+    fn rerolls() {
+        check_finite("2d6rr<3", 6, 12);
+        check_finite("2d6ro<3", 2, 12);
+        check_finite("2d6rr>0", 2, 12);
+    }
+
+    #[test]
+    fn explosions() {
+        check("1d6e6", Bound::Finite(1), Bound::Infinity);
+        check_finite("4d6e6kh3", 3, 18);
+        check("4d6e6pl1", Bound::Finite(3), Bound::Infinity);
+        check_finite("1d6e7", 1, 6);
+        check_finite("1d6ra6", 1, 12);
+    }
+
+    #[test]
+    fn sets() {
+        check_finite("(1d20, 1d20)kh1", 1, 20);
+        check_finite("(1d20, 5, -3)kl2", -2, 2);
+        check_finite("(1d4, 1d4)", 2, 8);
+        check_finite("(1d4, 1d4)k>2", 0, 8);
+    }
+
+    #[test]
+    fn fits_in_i64() {
+        let bounds = |input| {
+            Bounds::of(&Program::compile(ast::Root::cast(parser::parse(input).syntax()).unwrap()))
+        };
+
+        assert!(bounds("1d20 + 5").fits_in_i64());
+        assert!(!bounds("9223372036854775807 + 1d2").fits_in_i64());
+        assert!(!bounds("1d6e6").fits_in_i64());
+        assert_eq!(bounds("1d6e6").to_string(), "1 to unbounded");
+    }
+}
@@ -1,16 +1,30 @@
 use super::*;
 use la_arena::Arena;
-use syntax::SyntaxKind;
 use std::ops::{Index, IndexMut};
+use text_size::TextRange;
 
 
-#[derive(Debug, PartialEq, Default)]
+#[derive(Debug, Default)]
 pub struct Database {
     pub(super) exprs: Arena<Expression>,
     pub(super) ctx: RollContext,
+    pub(super) log: RollLog,
+    dice: usize,
+}
+
+/// Databases are equal if they hold the same expressions and drew the same dice in the same
+/// order, whatever state their contexts are in.
+impl PartialEq for Database {
+    fn eq(&self, other: &Self) -> bool {
+        self.exprs == other.exprs && self.log == other.log
+    }
 }
 
 impl Database {
+    pub(super) fn new(ctx: RollContext) -> Self {
+        Self { exprs: Arena::new(), ctx, log: RollLog::default(), dice: 0 }
+    }
+
     pub(super) fn get(&self, idx: ExprIdx) -> &Expression {
         self.exprs.index(idx)
     }
@@ -23,106 +37,76 @@ impl Database {
         self.exprs.alloc(expr)
     }
 
-    pub(super) fn roll(&mut self, sides: u64) -> u64 {
-        self.ctx.roll(sides)
+    /// Numbers a new die, counting from 0.
+    pub(super) fn next_die(&mut self) -> usize {
+        self.dice += 1;
+        self.dice - 1
     }
 
-    pub(super) fn roll_many(&mut self, sides: u64, count: u64) -> impl Iterator<Item=u64> {
-        self.ctx.roll_many(sides, count)
-    }
+    /// Rolls for `die` and records the draw in the log.
+    pub(super) fn roll(&mut self, sides: u64, die: usize, range: TextRange) -> u64 {
+        let value = self.ctx.roll(sides);
+        self.log.push(Draw { die, sides, value, range });
 
-    pub(super) fn lower_expr(&mut self, ast: Option<ast::Expr>) -> Expression {
-        let expr = if let Some(ast) = ast {
-            match ast {
-                ast::Expr::BinaryExpr(ast) => self.lower_binary(ast),
-                ast::Expr::Dice(ast) => self.lower_dice(ast),
-                ast::Expr::Literal(ast) => Expr::literal(ast.parse()),
-                ast::Expr::ParenExpr(ast) => self.lower_expr(ast.expr()).expr,
-                ast::Expr::Set(ast) => self.lower_set(ast),
-                ast::Expr::UnaryExpr(ast) => self.lower_unary(ast),
-            }
-        } else {
-            Expr::Missing
-        };
-
-        Expression::new(expr)
+        value
     }
 
-    fn lower_binary(&mut self, ast: ast::BinaryExpr) -> Expr {
-        let op = match ast.op().unwrap().kind() {
-            SyntaxKind::Plus => BinaryOp::Add,
-            SyntaxKind::Minus => BinaryOp::Sub,
-            SyntaxKind::Star => BinaryOp::Mul,
-            SyntaxKind::Slash => BinaryOp::Div,
-            _ => unreachable!(),
-        };
-
-        let lhs = self.lower_expr(ast.lhs());
-        let lhs = self.exprs.alloc(lhs);
-        
-        let rhs = self.lower_expr(ast.rhs());
-        let rhs = self.exprs.alloc(rhs);
-        
-        Expr::binary(op, lhs, rhs)
+    /// Rolls `program`, allocating everything it rolls in the database.
+    pub(super) fn lower(&mut self, program: &Program) -> Expression {
+        self.lower_node(program, program.root())
     }
 
-    fn lower_dice(&mut self, ast: ast::Dice) -> Expr {
-        let ops = ast.ops().map(|op| self.lower_set_op(op)).collect();
-
-        Expr::dice(ast.count(), ast.sides(), ops, self)
-    }
+    fn lower_node(&mut self, program: &Program, node: NodeIdx) -> Expression {
+        let expr = match &program[node] {
+            Node::Missing => Expr::Missing,
+            Node::Band { expr, bands } => {
+                let expr = self.lower_child(program, *expr);
 
-    fn lower_set(&mut self, ast: ast::Set) -> Expr {
-        let mut items = Vec::new();
+                Expr::band(expr, bands.clone())
+            }
+            Node::Binary { op, lhs, rhs } => {
+                let lhs = self.lower_child(program, *lhs);
+                let rhs = self.lower_child(program, *rhs);
 
-        for item in ast.items().into_iter() {
-            let item = self.lower_expr(Some(item));
-            let item = self.exprs.alloc(item);
+                Expr::binary(*op, lhs, rhs)
+            }
+            Node::Dice { count, sides, ops, range } => {
+                Expr::dice(*count, *sides, ops.clone(), *range, self)
+            }
+            Node::Literal(n) => Expr::literal(*n),
+            Node::Set { items, ops } => {
+                let items = items.iter().map(|item| self.lower_child(program, *item)).collect();
 
-            items.push(item);
-        }
+                Expr::set(items, ops.clone(), self)
+            }
+            Node::Tagged { tag, expr } => {
+                let expr = self.lower_child(program, *expr);
 
-        let ops = ast.ops().map(|op| self.lower_set_op(op)).collect();
+                Expr::tagged(tag.clone(), expr)
+            }
+            Node::Unary { op, expr } => {
+                let expr = self.lower_child(program, *expr);
 
-        Expr::set(items, ops)
-    }
+                Expr::unary(*op, expr)
+            }
+            Node::Versus { lhs, rhs } => {
+                let lhs = self.lower_child(program, *lhs);
+                let rhs = self.lower_child(program, *rhs);
 
-    fn lower_unary(&mut self, ast: ast::UnaryExpr) -> Expr {
-        let op = match ast.op().unwrap().kind() {
-            SyntaxKind::Minus => UnaryOp::Neg,
-            _ => unreachable!(),
+                Expr::versus(lhs, rhs)
+            }
         };
 
-        let expr = self.lower_expr(ast.expr());
-        let expr = self.exprs.alloc(expr);
-        
-        Expr::unary(op, expr)
-    }
-
-    fn lower_set_op(&mut self, ast: ast::SetOp) -> SetOperation {
-        let op = match ast.op().unwrap().kind() {
-            SyntaxKind::Keep => SetOp::Keep,
-            SyntaxKind::Drop => SetOp::Drop,
-            SyntaxKind::Reroll => SetOp::Reroll,
-            SyntaxKind::RerollOnce => SetOp::RerollOnce,
-            SyntaxKind::RerollAdd => SetOp::RerollAdd,
-            SyntaxKind::Explode => SetOp::Explode,
-            SyntaxKind::Min => SetOp::Min,
-            SyntaxKind::Max => SetOp::Max,
-            _ => unreachable!(),
-        };
+        let mut expression = Expression::new(expr);
+        expression.range = program.range(node);
+
+        expression
+    }
 
-        let sel = ast.sel()
-            .map_or(SetSel::Number,
-                    |token| match token.kind() {
-                        SyntaxKind::Highest => SetSel::Highest,
-                        SyntaxKind::Lowest => SetSel::Lowest,
-                        SyntaxKind::Greater => SetSel::Greater,
-                        SyntaxKind::Less => SetSel::Less,
-                        _ => unreachable!(),
-                    });
+    fn lower_child(&mut self, program: &Program, node: NodeIdx) -> ExprIdx {
+        let expr = self.lower_node(program, node);
 
-        SetOperation::new(op, sel, ast.num())
+        self.exprs.alloc(expr)
     }
 }
 
@@ -133,12 +117,11 @@ mod tests {
     use crate::SEED;
 
     fn default_db() -> Database {
-        Database {
-            exprs: Arena::new(),
-            ctx: RollContext::new(
-                StdRng::seed_from_u64(SEED)
-            ),
-        }
+        Database::new(RollContext::new(StdRng::seed_from_u64(SEED)))
+    }
+
+    fn range(start: u32, end: u32) -> TextRange {
+        TextRange::new(start.into(), end.into())
     }
 
     fn parse(input: &str) -> ast::Root {
@@ -146,10 +129,9 @@ mod tests {
     }
 
     fn check_expr(input: &str, expected_hir: Expression, expected_database: Database) {
-        let root = parse(input);
-        let expr = root.expr();
+        let program = Program::compile(parse(input));
         let mut database = default_db();
-        let hir = database.lower_expr(expr);
+        let hir = database.lower(&program);
 
         assert_eq!(hir, expected_hir);
         assert_eq!(database, expected_database);
@@ -181,7 +163,7 @@ mod tests {
     #[test]
     fn lower_dice_no_ops() {
         let mut db = default_db();
-        let expr = Expr::dice(Some(1), Some(12), Vec::new(), &mut db);
+        let expr = Expr::dice(Some(1), Some(12), Vec::new(), range(0, 4), &mut db);
 
         check_expr(
             "1d12",
@@ -193,7 +175,7 @@ mod tests {
     #[test]
     fn lower_dice_implicit_count() {
         let mut db = default_db();
-        let expr = Expr::dice(Some(1), Some(20), Vec::new(), &mut db);
+        let expr = Expr::dice(Some(1), Some(20), Vec::new(), range(0, 3), &mut db);
 
         check_expr(
             "d20",
@@ -205,7 +187,7 @@ mod tests {
     #[test]
     fn lower_percentage_dice() {
         let mut db = default_db();
-        let expr = Expr::dice(Some(3), Some(100), Vec::new(), &mut db);
+        let expr = Expr::dice(Some(3), Some(100), Vec::new(), range(0, 3), &mut db);
 
         check_expr(
             "3d%",
@@ -219,7 +201,7 @@ mod tests {
         let mut db = default_db();
         let expr = Expr::dice(Some(2), Some(20), vec![
             SetOperation::new(SetOp::Keep, SetSel::Highest, Some(1))
-        ], &mut db);
+        ], range(0, 7), &mut db);
 
         check_expr(
             "2d20kh1",
@@ -235,7 +217,7 @@ mod tests {
             SetOperation::new(SetOp::Drop, SetSel::Lowest, Some(1)),
             SetOperation::new(SetOp::RerollOnce, SetSel::Less, Some(2)),
             SetOperation::new(SetOp::Explode, SetSel::Number, Some(5)),
-        ], &mut db);
+        ], range(0, 13), &mut db);
 
         check_expr(
             "2d20pl1ro<2e5",
@@ -244,6 +226,21 @@ mod tests {
         );
     }
 
+    #[test]
+    fn lower_dice_crit_ranges() {
+        let mut db = default_db();
+        let expr = Expr::dice(Some(1), Some(20), vec![
+            SetOperation::new(SetOp::CritSuccess, SetSel::Greater, Some(19)),
+            SetOperation::new(SetOp::CritFail, SetSel::Number, Some(1)),
+        ], range(0, 12), &mut db);
+
+        check_expr(
+            "1d20cs>19cf1",
+            Expression::new(expr),
+            db,
+        );
+    }
+
     #[test]
     fn lower_literal() {
         let expr = Expr::literal(Some(999));
@@ -257,12 +254,13 @@ mod tests {
 
     #[test]
     fn lower_empty_set() {
-        let expr = Expr::set(Vec::new(), Vec::new());
+        let mut db = default_db();
+        let expr = Expr::set(Vec::new(), Vec::new(), &mut db);
 
         check_expr(
             "()",
             Expression::new(expr),
-            default_db(),
+            db,
         );
     }
 
@@ -274,7 +272,7 @@ mod tests {
         ];
 
         assert_eq!(items.len(), 1);
-        let expr = Expr::set(items, Vec::new());
+        let expr = Expr::set(items, Vec::new(), &mut db);
 
         check_expr(
             "(2,)",
@@ -287,14 +285,14 @@ mod tests {
     fn lower_set() {
         let mut db = default_db();
         let items: Vec<ExprIdx> = vec![
-            Expr::dice(Some(8), Some(6), Vec::new(), &mut db),
+            Expr::dice(Some(8), Some(6), Vec::new(), range(1, 4), &mut db),
             Expr::literal(Some(3)),
         ].into_iter()
             .map(|expr| alloc(&mut db, expr))
             .collect();
 
         assert_eq!(items.len(), 2);
-        let expr = Expr::set(items, Vec::new());
+        let expr = Expr::set(items, Vec::new(), &mut db);
 
         check_expr(
             "(8d6, 3)",
@@ -311,13 +309,13 @@ mod tests {
         let item = Expr::literal(Some(100));
         items.push(alloc(&mut db, item));
 
-        let item = Expr::dice(Some(2), Some(100), Vec::new(), &mut db);
+        let item = Expr::dice(Some(2), Some(100), Vec::new(), range(6, 11), &mut db);
         items.push(alloc(&mut db, item));
 
         assert_eq!(items.len(), 2);
         let expr = Expr::set(items, vec![
             SetOperation::new(SetOp::Explode, SetSel::Number, Some(100))
-        ]);
+        ], &mut db);
 
         check_expr(
             "(100, 2d100)e100",
@@ -329,7 +327,7 @@ mod tests {
     #[test]
     fn lower_unary_expr() {
         let mut db = default_db();
-        let inner = Expr::dice(Some(3), Some(4), Vec::new(), &mut db);
+        let inner = Expr::dice(Some(3), Some(4), Vec::new(), range(1, 4), &mut db);
         let inner = alloc(&mut db, inner);
 
         let expr = Expr::unary(UnaryOp::Neg, inner);
@@ -341,6 +339,58 @@ mod tests {
         );
     }
 
+    #[test]
+    fn lower_tagged_expr() {
+        let mut db = default_db();
+        let inner = Expr::dice(Some(2), Some(6), Vec::new(), range(0, 3), &mut db);
+        let inner = alloc(&mut db, inner);
+
+        let expr = Expr::tagged("fire".to_string(), inner);
+
+        check_expr(
+            "2d6[fire]",
+            Expression::new(expr),
+            db,
+        );
+    }
+
+    #[test]
+    fn lower_versus_expr() {
+        let mut db = default_db();
+        let lhs = Expr::dice(Some(1), Some(20), Vec::new(), range(0, 4), &mut db);
+        let lhs = alloc(&mut db, lhs);
+        let rhs = alloc(&mut db, Expr::literal(Some(15)));
+
+        let expr = Expr::versus(lhs, rhs);
+
+        check_expr(
+            "1d20 vs 15",
+            Expression::new(expr),
+            db,
+        );
+    }
+
+    #[test]
+    fn lower_band_expr() {
+        let mut db = default_db();
+        let inner = Expr::dice(Some(2), Some(6), Vec::new(), range(0, 3), &mut db);
+        let inner = alloc(&mut db, inner);
+
+        let expr = Expr::band(inner, vec![
+            BandArm::new(None, Some(6), "miss".to_string()),
+            BandArm::new(Some(7), Some(9), "partial".to_string()),
+            BandArm::new(Some(10), None, "hit".to_string()),
+            BandArm::new(None, Some(-1), "never".to_string()),
+            BandArm::new(Some(12), Some(12), "boxcars".to_string()),
+        ]);
+
+        check_expr(
+            "2d6 -> {<=6: miss, 7..9: partial, >=10: hit, <0: never, 12: boxcars}",
+            Expression::new(expr),
+            db,
+        );
+    }
+
     #[test]
     fn lower_binary_expr_without_rhs() {
         let mut db = default_db();
@@ -0,0 +1,804 @@
+use crate::{BinaryOp, Node, NodeIdx, Program, SetOp, SetOperation, SetSel, UnaryOp, MAX_REPEATS};
+use crate::Histogram;
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+
+
+/// The most distinct totals a distribution may have before working it out is given up on.
+const MAX_SUPPORT: usize = 1 << 16;
+
+/// Roughly how many steps working out a distribution may take before it is given up on.
+const MAX_WORK: u64 = 50_000_000;
+
+
+/// The probability of every total an expression can roll.
+///
+/// A distribution is never empty, and only holds totals with a nonzero probability.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Distribution {
+    probs: BTreeMap<i64, f64>,
+}
+
+impl Distribution {
+    /// Works out the exact distribution of `program`'s totals, or `None` if that isn't feasible.
+    ///
+    /// Dice are handled exactly as long as every operation on them acts on each die on its own
+    /// (comparison selectors, `mi`, and `ma`), apart from an optional last operation that keeps
+    /// or drops the highest or lowest dice, or explodes them. Sets are handled exactly when they
+    /// are small enough to try every combination of their items. Anything else, and anything
+    /// that would take too long, gives `None`. So does an expression that could divide by zero.
+    ///
+    /// Pools of zero dice are taken to be empty, as with the default `ZeroDicePolicy`.
+    pub fn exact(program: &Program) -> Option<Self> {
+        Engine { program, work: 0 }.node(program.root())
+    }
+
+    /// The observed distribution of a simulation's totals, or `None` if nothing was rolled.
+    ///
+    /// Unlike an exact distribution, it isn't limited to `MAX_SUPPORT` totals, since it can't
+    /// have more totals than there were samples.
+    pub(crate) fn from_histogram(histogram: &Histogram) -> Option<Self> {
+        let samples: u64 = histogram.values().sum();
+
+        let probs: BTreeMap<_, _> = histogram
+            .iter()
+            .filter(|(_, &count)| count > 0)
+            .map(|(&total, &count)| (total, count as f64 / samples as f64))
+            .collect();
+
+        if probs.is_empty() {
+            None
+        } else {
+            Some(Self { probs })
+        }
+    }
+
+    fn point(total: i64) -> Self {
+        Self { probs: BTreeMap::from([(total, 1.0)]) }
+    }
+
+    fn from_map(mut probs: BTreeMap<i64, f64>) -> Option<Self> {
+        probs.retain(|_, prob| *prob > 0.0);
+
+        if probs.is_empty() || probs.len() > MAX_SUPPORT {
+            None
+        } else {
+            Some(Self { probs })
+        }
+    }
+
+    pub fn min(&self) -> i64 {
+        *self.probs.keys().next().unwrap()
+    }
+
+    pub fn max(&self) -> i64 {
+        *self.probs.keys().next_back().unwrap()
+    }
+
+    /// The probability of rolling exactly `total`.
+    pub fn probability(&self, total: i64) -> f64 {
+        self.probs.get(&total).copied().unwrap_or(0.0)
+    }
+
+    /// Every total that can be rolled alongside its probability, from lowest to highest.
+    pub fn iter(&self) -> impl Iterator<Item=(i64, f64)> + '_ {
+        self.probs.iter().map(|(&total, &prob)| (total, prob))
+    }
+
+    pub fn mean(&self) -> f64 {
+        self.iter().map(|(total, prob)| total as f64 * prob).sum()
+    }
+
+    pub fn variance(&self) -> f64 {
+        let mean = self.mean();
+
+        self.iter().map(|(total, prob)| (total as f64 - mean).powi(2) * prob).sum()
+    }
+
+    /// The smallest total that is rolled at or below with probability at least `q`.
+    ///
+    /// Panics if `q` isn't between 0 and 1.
+    pub fn quantile(&self, q: f64) -> i64 {
+        assert!((0.0..=1.0).contains(&q), "quantile {} is not between 0 and 1", q);
+
+        // Allows for rounding errors, so that the median of 3d6 is 10 rather than 11.
+        let q = q - 1e-9;
+        let mut seen = 0.0;
+
+        self.iter()
+            .find(|(_, prob)| {
+                seen += prob;
+                seen >= q
+            })
+            .map_or(self.max(), |(total, _)| total)
+    }
+
+    /// The probabilities that a total drawn from this distribution is greater than, equal to, or
+    /// less than one drawn independently from `other`.
+    pub fn compare(&self, other: &Distribution) -> Comparison {
+        let mut comparison = Comparison { greater: 0.0, equal: 0.0, less: 0.0 };
+
+        let mut others = other.iter().peekable();
+        let mut below = 0.0;
+
+        for (total, prob) in self.iter() {
+            while let Some(&(other_total, other_prob)) = others.peek() {
+                if other_total >= total {
+                    break;
+                }
+
+                below += other_prob;
+                others.next();
+            }
+
+            let equal = others.peek()
+                .filter(|(other_total, _)| *other_total == total)
+                .map_or(0.0, |(_, other_prob)| *other_prob);
+
+            comparison.greater += prob * below;
+            comparison.equal += prob * equal;
+            comparison.less += prob * (1.0 - below - equal).max(0.0);
+        }
+
+        comparison
+    }
+
+    fn len(&self) -> usize {
+        self.probs.len()
+    }
+
+    fn map(&self, f: impl Fn(i64) -> Option<i64>) -> Option<Self> {
+        let mut probs = BTreeMap::new();
+
+        for (total, prob) in self.iter() {
+            *probs.entry(f(total)?).or_insert(0.0) += prob;
+        }
+
+        Self::from_map(probs)
+    }
+}
+
+
+/// How likely one total is to beat another, like an attack roll against a defence roll.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Comparison {
+    pub greater: f64,
+    pub equal: f64,
+    pub less: f64,
+}
+
+
+struct Engine<'a> {
+    program: &'a Program,
+    work: u64,
+}
+
+impl Engine<'_> {
+    fn spend(&mut self, work: usize) -> Option<()> {
+        self.work = self.work.saturating_add(work as u64);
+
+        (self.work <= MAX_WORK).then_some(())
+    }
+
+    fn node(&mut self, idx: NodeIdx) -> Option<Distribution> {
+        match &self.program[idx] {
+            Node::Missing | Node::Literal(None) => Some(Distribution::point(0)),
+            Node::Literal(Some(n)) => Some(Distribution::point(*n as i64)),
+            Node::Band { expr, .. } | Node::Tagged { expr, .. } => self.node(*expr),
+            Node::Binary { op, lhs, rhs } => {
+                let (lhs, rhs) = (self.node(*lhs)?, self.node(*rhs)?);
+
+                match op {
+                    BinaryOp::Add => self.combine(&lhs, &rhs, i64::checked_add),
+                    BinaryOp::Sub => self.combine(&lhs, &rhs, i64::checked_sub),
+                    BinaryOp::Mul => self.combine(&lhs, &rhs, i64::checked_mul),
+                    // Dividing by zero fails, so a divisor that can be zero gives up.
+                    BinaryOp::Div => self.combine(&lhs, &rhs, i64::checked_div),
+                }
+            }
+            Node::Dice { count, sides, ops, .. } => self.dice(*count, *sides, ops),
+            Node::Set { items, ops } => self.set(items, ops),
+            Node::Unary { op: UnaryOp::Neg, expr } => self.node(*expr)?.map(i64::checked_neg),
+            Node::Versus { lhs, rhs } => {
+                let (lhs, rhs) = (self.node(*lhs)?, self.node(*rhs)?);
+
+                self.combine(&lhs, &rhs, i64::checked_sub)
+            }
+        }
+    }
+
+    /// The distribution of `f(lhs, rhs)`, where `lhs` and `rhs` are independent. Either may be
+    /// unnormalized, in which case so is the result.
+    fn combine(&mut self, lhs: &Distribution, rhs: &Distribution,
+               f: impl Fn(i64, i64) -> Option<i64>) -> Option<Distribution> {
+        self.spend(lhs.len() * rhs.len())?;
+
+        let mut probs = BTreeMap::new();
+
+        for (a, pa) in lhs.iter() {
+            for (b, pb) in rhs.iter() {
+                *probs.entry(f(a, b)?).or_insert(0.0) += pa * pb;
+            }
+        }
+
+        Distribution::from_map(probs)
+    }
+
+    /// The distribution of the sum of `n` independent totals drawn from `dist`.
+    fn sum_of(&mut self, dist: Distribution, mut n: u64) -> Option<Distribution> {
+        let mut sum = Distribution::point(0);
+        let mut power = dist;
+
+        while n > 0 {
+            if n & 1 == 1 {
+                sum = self.combine(&sum, &power, i64::checked_add)?;
+            }
+
+            n >>= 1;
+
+            if n > 0 {
+                power = self.combine(&power, &power, i64::checked_add)?;
+            }
+        }
+
+        Some(sum)
+    }
+
+    fn dice(&mut self, count: Option<u64>, sides: Option<u64>,
+            ops: &[SetOperation]) -> Option<Distribution> {
+        let (count, sides) = match (count, sides) {
+            (Some(count), Some(sides)) if count > 0 => (count, sides),
+            _ => return Some(Distribution::point(0)),
+        };
+
+        if sides == 0 || sides > MAX_SUPPORT as u64 {
+            return None;
+        }
+
+        // Crit ranges don't change the total, and operations without a number are never applied.
+        let mut ops = ops.iter()
+            .filter(|op| op.num.is_some())
+            .filter(|op| !matches!(op.op, SetOp::CritSuccess | SetOp::CritFail));
+
+        let mut die = DieStates::uniform(sides);
+        self.spend(sides as usize)?;
+
+        let last = loop {
+            match ops.next() {
+                Some(op) if die.apply(op, sides) => self.spend(die.len())?,
+                last => break last,
+            }
+        };
+
+        if ops.next().is_some() {
+            return None;
+        }
+
+        match last.map(|op| (op.op, op.sel)) {
+            None => self.sum_of(die.totals()?, count),
+            Some((SetOp::Keep | SetOp::Drop, SetSel::Highest | SetSel::Lowest)) => {
+                self.keep_or_drop(&die, count, last.unwrap())
+            }
+            Some((_, SetSel::Highest | SetSel::Lowest)) => None,
+            Some((SetOp::Explode | SetOp::RerollAdd, _)) => {
+                let contribution = self.explode(&die, sides, last.unwrap())?;
+                self.sum_of(contribution, count)
+            }
+            Some(_) => None,
+        }
+    }
+
+    /// The total that one die contributes once `op` has exploded it, extra dice included.
+    fn explode(&mut self, die: &DieStates, sides: u64, op: &SetOperation) -> Option<Distribution> {
+        let rounds = if op.op == SetOp::Explode { MAX_REPEATS } else { 1 };
+        let face = 1.0 / sides as f64;
+
+        let (exploding, steady): (Vec<_>, Vec<_>) = (1..=sides as i64).partition(|&f| op.matches(f));
+        let exploding = Distribution::from_map(exploding.iter().map(|&f| (f, face)).collect());
+        let steady: BTreeMap<_, _> = steady.iter().map(|&f| (f, face)).collect();
+
+        // The total of an extra die and any extra dice it explodes into, with `k` rounds of
+        // exploding left after its own.
+        let mut chain = DieStates::uniform(sides).totals()?;
+
+        for _ in 1..rounds {
+            let mut next = steady.clone();
+
+            if let Some(exploding) = &exploding {
+                for (total, prob) in self.combine(exploding, &chain, i64::checked_add)?.iter() {
+                    *next.entry(total).or_insert(0.0) += prob;
+                }
+            }
+
+            chain = Distribution::from_map(next)?;
+        }
+
+        let mut contribution = BTreeMap::new();
+        let mut exploded = BTreeMap::new();
+
+        for (value, kept, prob) in die.iter() {
+            let total = if kept { value } else { 0 };
+            let target = if op.matches(value) { &mut exploded } else { &mut contribution };
+
+            *target.entry(total).or_insert(0.0) += prob;
+        }
+
+        if let Some(exploded) = Distribution::from_map(exploded) {
+            for (total, prob) in self.combine(&exploded, &chain, i64::checked_add)?.iter() {
+                *contribution.entry(total).or_insert(0.0) += prob;
+            }
+        }
+
+        Distribution::from_map(contribution)
+    }
+
+    /// The total of `count` dice once `op` has kept or dropped the highest or lowest of them.
+    ///
+    /// The values a die can show are gone through from the first to be selected to the last,
+    /// tracking how many dice show each one. The first `num` dice are the ones selected, and
+    /// each die counts towards the total if it is kept by `op` and wasn't dropped before.
+    fn keep_or_drop(&mut self, die: &DieStates, count: u64,
+                    op: &SetOperation) -> Option<Distribution> {
+        let count = usize::try_from(count).ok()?;
+        let selected = usize::try_from(op.num?).unwrap_or(usize::MAX).min(count);
+        let is_keep = op.op == SetOp::Keep;
+
+        let mut values = die.by_value();
+        if op.sel == SetSel::Highest {
+            values.reverse();
+        }
+
+        // `dp[i]` holds the distribution of the counted total after placing `i` dice.
+        let mut dp = vec![BTreeMap::new(); count + 1];
+        dp[0].insert(0, 1.0);
+
+        for (value, prob, kept) in values {
+            let mut next = vec![BTreeMap::new(); count + 1];
+
+            for (placed, totals) in dp.iter().enumerate() {
+                let remaining = count - placed;
+                self.spend(totals.len() * (remaining + 1))?;
+
+                for (&total, &p) in totals.iter() {
+                    let mut ways = 1.0;
+
+                    for c in 0..=remaining {
+                        if c > 0 {
+                            ways *= (remaining - c + 1) as f64 / c as f64;
+                        }
+
+                        let p = p * ways * prob.powi(c as i32);
+                        if p == 0.0 {
+                            continue;
+                        }
+
+                        let taken = c.min(selected.saturating_sub(placed));
+                        let counted = if is_keep { taken } else { c - taken };
+
+                        for (j, q) in binomial(counted, kept) {
+                            let total = total + value.checked_mul(j as i64)?;
+                            *next[placed + c].entry(total).or_insert(0.0) += p * q;
+                        }
+                    }
+                }
+            }
+
+            dp = next;
+        }
+
+        Distribution::from_map(dp.pop()?)
+    }
+
+    fn set(&mut self, items: &[NodeIdx], ops: &[SetOperation]) -> Option<Distribution> {
+        let items = items.iter()
+            .map(|item| self.node(*item))
+            .collect::<Option<Vec<_>>>()?;
+
+        // Keep and Drop are the only operations that apply to sets; any others are ignored.
+        let ops: Vec<_> = ops.iter()
+            .filter(|op| op.num.is_some() && matches!(op.op, SetOp::Keep | SetOp::Drop))
+            .collect();
+
+        if ops.is_empty() {
+            return items.iter().try_fold(Distribution::point(0), |sum, item| {
+                self.combine(&sum, item, i64::checked_add)
+            });
+        }
+
+        let combinations = items.iter().try_fold(1usize, |n, item| n.checked_mul(item.len()))?;
+        self.spend(combinations.checked_mul(items.len() * ops.len())?)?;
+
+        let items: Vec<Vec<_>> = items.iter().map(|item| item.iter().collect()).collect();
+        let mut choice = vec![0; items.len()];
+        let mut probs = BTreeMap::new();
+
+        loop {
+            let values: Vec<_> = choice.iter().zip(&items).map(|(&i, item)| item[i].0).collect();
+            let prob: f64 = choice.iter().zip(&items).map(|(&i, item)| item[i].1).product();
+
+            *probs.entry(set_total(&values, &ops)).or_insert(0.0) += prob;
+
+            // Moves on to the next combination, like an odometer.
+            let mut i = 0;
+            loop {
+                if i == items.len() {
+                    return Distribution::from_map(probs);
+                }
+
+                choice[i] += 1;
+                if choice[i] < items[i].len() {
+                    break;
+                }
+
+                choice[i] = 0;
+                i += 1;
+            }
+        }
+    }
+}
+
+/// The total of a set whose items rolled `values`, after `ops` have kept and dropped items.
+/// Mirrors `set_ops::operate_on_set`.
+fn set_total(values: &[i64], ops: &[&SetOperation]) -> i64 {
+    let mut kept = vec![true; values.len()];
+
+    for op in ops {
+        let num = op.num.unwrap();
+        let seen = |i: usize| if kept[i] { values[i] } else { 0 };
+
+        let selection: Vec<usize> = match op.sel {
+            SetSel::Highest | SetSel::Lowest => {
+                let mut order: Vec<_> = (0..values.len()).collect();
+
+                if op.sel == SetSel::Highest {
+                    order.sort_by_key(|&i| Reverse(seen(i)));
+                } else {
+                    order.sort_by_key(|&i| seen(i));
+                }
+
+                order.into_iter().take(num as usize).collect()
+            }
+            _ => (0..values.len()).filter(|&i| op.matches(seen(i))).collect(),
+        };
+
+        let is_drop = op.op == SetOp::Drop;
+
+        for (i, kept) in kept.iter_mut().enumerate() {
+            if selection.contains(&i) == is_drop {
+                *kept = false;
+            }
+        }
+    }
+
+    values.iter().zip(kept).filter(|(_, kept)| *kept).map(|(value, _)| value).sum()
+}
+
+/// The probability of each number of successes in `n` trials that each succeed with probability
+/// `p`, leaving out impossible numbers.
+fn binomial(n: usize, p: f64) -> Vec<(usize, f64)> {
+    if p >= 1.0 {
+        return vec![(n, 1.0)];
+    } else if p <= 0.0 {
+        return vec![(0, 1.0)];
+    }
+
+    let mut ways = 1.0;
+
+    (0..=n)
+        .map(|j| {
+            if j > 0 {
+                ways *= (n - j + 1) as f64 / j as f64;
+            }
+
+            (j, ways * p.powi(j as i32) * (1.0 - p).powi((n - j) as i32))
+        })
+        .collect()
+}
+
+
+/// The distribution of a single die's value, and of whether it is still kept, after the
+/// operations that act on each die on its own.
+#[derive(Debug, Clone)]
+struct DieStates {
+    kept: BTreeMap<i64, f64>,
+    dropped: BTreeMap<i64, f64>,
+}
+
+impl DieStates {
+    fn uniform(sides: u64) -> Self {
+        let face = 1.0 / sides as f64;
+
+        Self {
+            kept: (1..=sides as i64).map(|f| (f, face)).collect(),
+            dropped: BTreeMap::new(),
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.kept.len() + self.dropped.len()
+    }
+
+    /// Every value and kept status a die can have, alongside its probability.
+    fn iter(&self) -> impl Iterator<Item=(i64, bool, f64)> + '_ {
+        let kept = self.kept.iter().map(|(&value, &prob)| (value, true, prob));
+        let dropped = self.dropped.iter().map(|(&value, &prob)| (value, false, prob));
+
+        kept.chain(dropped)
+    }
+
+    /// Every value a die can show, from lowest to highest, with its probability and the chance
+    /// that a die showing it is still kept.
+    fn by_value(&self) -> Vec<(i64, f64, f64)> {
+        let mut values: BTreeMap<i64, (f64, f64)> = BTreeMap::new();
+
+        for (value, kept, prob) in self.iter() {
+            let (total, kept_prob) = values.entry(value).or_insert((0.0, 0.0));
+
+            *total += prob;
+            if kept {
+                *kept_prob += prob;
+            }
+        }
+
+        values.into_iter()
+            .map(|(value, (prob, kept))| (value, prob, kept / prob))
+            .collect()
+    }
+
+    /// The total a die contributes: its value if it is kept, or 0 if it was dropped.
+    fn totals(&self) -> Option<Distribution> {
+        let mut totals = self.kept.clone();
+        *totals.entry(0).or_insert(0.0) += self.dropped.values().sum::<f64>();
+
+        Distribution::from_map(totals)
+    }
+
+    /// Applies `op` to the die, or returns false if it doesn't act on each die on its own.
+    fn apply(&mut self, op: &SetOperation, sides: u64) -> bool {
+        let num = op.num.unwrap() as i64;
+
+        match (op.op, op.sel) {
+            // `mi` and `ma` ignore the selector.
+            (SetOp::Min, _) => self.map_values(|value| value.max(num)),
+            (SetOp::Max, _) => self.map_values(|value| value.min(num)),
+            (_, SetSel::Highest | SetSel::Lowest) => return false,
+            (SetOp::Keep, _) => self.drop_where(|value| !op.matches(value)),
+            (SetOp::Drop, _) => self.drop_where(|value| op.matches(value)),
+            (SetOp::Reroll, _) => self.reroll(op, sides, MAX_REPEATS),
+            (SetOp::RerollOnce, _) => self.reroll(op, sides, 1),
+            _ => return false,
+        }
+
+        true
+    }
+
+    fn map_values(&mut self, f: impl Fn(i64) -> i64) {
+        for states in [&mut self.kept, &mut self.dropped] {
+            let mut mapped = BTreeMap::new();
+
+            for (value, prob) in states.iter() {
+                *mapped.entry(f(*value)).or_insert(0.0) += prob;
+            }
+
+            *states = mapped;
+        }
+    }
+
+    fn drop_where(&mut self, f: impl Fn(i64) -> bool) {
+        let (dropped, kept) = self.kept.iter().partition(|(value, _)| f(**value));
+        self.kept = kept;
+
+        for (value, prob) in dropped {
+            *self.dropped.entry(value).or_insert(0.0) += prob;
+        }
+    }
+
+    /// Rerolls dice whose values match `op`, up to `times` times in a row, keeping whether each
+    /// die is kept.
+    fn reroll(&mut self, op: &SetOperation, sides: u64, times: usize) {
+        let face = 1.0 / sides as f64;
+        let matching = (1..=sides as i64).filter(|&f| op.matches(f)).count();
+        let p = matching as f64 / sides as f64;
+
+        // A reroll stops at the first value that doesn't match, or after the last reroll.
+        let stops: f64 = (0..times).map(|i| p.powi(i as i32)).sum();
+        let rerolled: Vec<_> = (1..=sides as i64)
+            .map(|f| {
+                let prob = if op.matches(f) { p.powi(times as i32 - 1) } else { stops };
+                (f, face * prob)
+            })
+            .collect();
+
+        for states in [&mut self.kept, &mut self.dropped] {
+            let (matched, mut rest): (BTreeMap<_, _>, BTreeMap<_, _>) =
+                states.iter().partition(|(value, _)| op.matches(**value));
+            let matched: f64 = matched.values().sum();
+
+            if matched > 0.0 {
+                for &(f, prob) in rerolled.iter() {
+                    *rest.entry(f).or_insert(0.0) += matched * prob;
+                }
+            }
+
+            *states = rest;
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{simulate_with_seed, SEED};
+
+    fn compile(input: &str) -> Program {
+        Program::compile(ast::Root::cast(parser::parse(input).syntax()).unwrap())
+    }
+
+    fn exact(input: &str) -> Distribution {
+        Distribution::exact(&compile(input)).unwrap()
+    }
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!((actual - expected).abs() < 1e-9, "expected {}, got {}", expected, actual);
+    }
+
+    fn assert_same(lhs: &Distribution, rhs: &Distribution) {
+        assert_eq!(lhs.probs.keys().collect::<Vec<_>>(), rhs.probs.keys().collect::<Vec<_>>());
+
+        for (total, prob) in lhs.iter() {
+            assert_close(prob, rhs.probability(total));
+        }
+    }
+
+    /// Checks the exact distribution against the frequencies of a large simulation.
+    fn check_against_simulation(input: &str) {
+        let program = compile(input);
+        let dist = Distribution::exact(&program).unwrap();
+        let simulation = simulate_with_seed(&program, 50_000, SEED).unwrap();
+
+        assert_close(dist.iter().map(|(_, prob)| prob).sum(), 1.0);
+
+        for (total, count) in simulation.histogram {
+            let frequency = count as f64 / simulation.samples as f64;
+            let prob = dist.probability(total);
+
+            assert!(
+                (frequency - prob).abs() < 0.01,
+                "{}: total {} was rolled {} of the time, but has probability {}",
+                input, total, frequency, prob,
+            );
+        }
+    }
+
+    #[test]
+    fn single_die() {
+        let dist = exact("1d6");
+
+        assert_eq!((dist.min(), dist.max()), (1, 6));
+        (1..=6).for_each(|total| assert_close(dist.probability(total), 1.0 / 6.0));
+    }
+
+    #[test]
+    fn sum_of_dice() {
+        let dist = exact("3d6");
+
+        assert_eq!((dist.min(), dist.max()), (3, 18));
+        assert_close(dist.probability(10), 27.0 / 216.0);
+        assert_close(dist.mean(), 10.5);
+        assert_close(dist.variance(), 8.75);
+        assert_eq!(dist.quantile(0.5), 10);
+    }
+
+    #[test]
+    fn arithmetic() {
+        assert_close(exact("2 * 1d6 - 1").mean(), 6.0);
+        assert_eq!(exact("-1d4").min(), -4);
+        assert_close(exact("1d6 / 2").probability(0), 1.0 / 6.0);
+        assert_close(exact("1d20 vs 10").mean(), 0.5);
+    }
+
+    #[test]
+    fn keep_highest() {
+        let dist = exact("2d20kh1");
+
+        assert_close(dist.probability(20), 39.0 / 400.0);
+        assert_close(dist.mean(), 13.825);
+        assert_close(exact("2d20kl1").mean(), 7.175);
+        assert_close(exact("4d6kh3").mean(), 15869.0 / 1296.0);
+    }
+
+    #[test]
+    fn drop_is_keep_of_the_rest() {
+        assert_same(&exact("4d6pl1"), &exact("4d6kh3"));
+        assert_same(&exact("5d8ph2"), &exact("5d8kl3"));
+    }
+
+    #[test]
+    fn set_matches_pool() {
+        assert_same(&exact("(1d20, 1d20)kh1"), &exact("2d20kh1"));
+        assert_same(&exact("(1d6, 2, 3)"), &exact("1d6 + 5"));
+    }
+
+    #[test]
+    fn reroll_once() {
+        let dist = exact("1d6ro1");
+
+        assert_close(dist.probability(1), 1.0 / 36.0);
+        (2..=6).for_each(|total| assert_close(dist.probability(total), 7.0 / 36.0));
+    }
+
+    #[test]
+    fn reroll_until_no_match() {
+        let dist = exact("1d6rr1");
+
+        assert_close(dist.probability(1), 0.0);
+        (2..=6).for_each(|total| assert_close(dist.probability(total), 0.2));
+    }
+
+    #[test]
+    fn clamp() {
+        assert_close(exact("1d6mi3").probability(3), 0.5);
+        assert_close(exact("1d6ma2").probability(2), 5.0 / 6.0);
+    }
+
+    #[test]
+    fn explode() {
+        let dist = exact("1d6e6");
+
+        assert_close(dist.probability(6), 0.0);
+        assert_close(dist.probability(7), 1.0 / 36.0);
+        assert_close(dist.mean(), 4.2);
+    }
+
+    #[test]
+    fn crit_ranges_are_ignored() {
+        assert_same(&exact("1d20cs>18cf<3"), &exact("1d20"));
+    }
+
+    #[test]
+    fn matches_simulation() {
+        check_against_simulation("4d6kh3 + 1d8ro<3");
+        check_against_simulation("(2d6, 1d12)kh1 * 2 - 1d4");
+        check_against_simulation("3d6e>4");
+        check_against_simulation("2d10ra10 + 1d6mi3ma5");
+        check_against_simulation("4d6k>2 + 3d6p<3rr1");
+        check_against_simulation("5d6k>2kh2");
+        check_against_simulation("(1d6, 1d6, 1d6)k>2kl1");
+    }
+
+    #[test]
+    fn compare() {
+        let comparison = exact("2d6").compare(&exact("1d12"));
+
+        assert_close(comparison.greater, 0.5);
+        assert_close(comparison.equal, 1.0 / 12.0);
+        assert_close(comparison.less, 5.0 / 12.0);
+    }
+
+    #[test]
+    fn compare_is_antisymmetric() {
+        let (lhs, rhs) = (exact("1d20 + 5"), exact("3d6kh2 + 8"));
+        let (forward, backward) = (lhs.compare(&rhs), rhs.compare(&lhs));
+
+        assert_close(forward.greater, backward.less);
+        assert_close(forward.equal, backward.equal);
+        assert_close(forward.greater + forward.equal + forward.less, 1.0);
+    }
+
+    #[test]
+    fn compare_without_overlap() {
+        let comparison = exact("1d4").compare(&exact("1d4 + 10"));
+
+        assert_eq!(comparison, Comparison { greater: 0.0, equal: 0.0, less: 1.0 });
+    }
+
+    #[test]
+    fn unsupported_expressions() {
+        assert_eq!(Distribution::exact(&compile("1d6 / (1d2 - 1)")), None);
+        assert_eq!(Distribution::exact(&compile("4d6kh3e6")), None);
+        assert_eq!(Distribution::exact(&compile("2d6roh1")), None);
+        assert_eq!(Distribution::exact(&compile("1d1000000000000")), None);
+    }
+}
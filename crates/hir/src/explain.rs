@@ -0,0 +1,295 @@
+use crate::{BandArm, BinaryOp, Node, NodeIdx, Program, SetOp, SetOperation, SetSel, UnaryOp};
+
+
+/// Describes `ast` in plain English, one step at a time: `4d6kh3+2` is "roll four six-sided
+/// dice, keep the highest three, add 2".
+pub fn explain(ast: ast::Root) -> String {
+    Program::compile(ast).explain()
+}
+
+impl Program {
+    /// Describes the program in plain English. See `explain`.
+    pub fn explain(&self) -> String {
+        steps(self, self.root()).join(", ")
+    }
+}
+
+
+/// The steps that work out the total of a node, in order.
+fn steps(program: &Program, idx: NodeIdx) -> Vec<String> {
+    match &program[idx] {
+        Node::Missing => vec!["?".to_string()],
+        Node::Band { expr, bands } => {
+            let mut steps = steps(program, *expr);
+            steps.extend(bands.iter().map(band));
+            steps
+        }
+        Node::Binary { op, lhs, rhs } => {
+            let verb = match op {
+                BinaryOp::Add => "add",
+                BinaryOp::Sub => "subtract",
+                BinaryOp::Mul => "multiply by",
+                BinaryOp::Div => "divide by",
+            };
+
+            let mut steps = steps(program, *lhs);
+            steps.push(match (op, &program[*rhs]) {
+                // Adding -2 reads better as subtracting 2.
+                (BinaryOp::Add, Node::Unary { op: UnaryOp::Neg, expr })
+                | (BinaryOp::Sub, Node::Unary { op: UnaryOp::Neg, expr })
+                    if matches!(program[*expr], Node::Literal(_)) =>
+                {
+                    let verb = if *op == BinaryOp::Add { "subtract" } else { "add" };
+                    format!("{} {}", verb, operand(program, *expr))
+                }
+                _ => format!("{} {}", verb, operand(program, *rhs)),
+            });
+            steps
+        }
+        Node::Dice { count, sides, ops, .. } => {
+            let mut steps = vec![format!("roll {}", dice(*count, *sides))];
+            steps.extend(ops.iter().map(|op| set_op(op, ("die", "dice"))));
+            steps
+        }
+        Node::Literal(n) => vec![format!("take {}", value(*n))],
+        Node::Set { items, ops } => {
+            let items: Vec<_> = items.iter().map(|item| operand(program, *item)).collect();
+
+            let mut steps = vec![format!("take {}", list(&items))];
+            // Only keeping and dropping do anything to a set; the rest are left in so the
+            // explanation still covers everything that was written.
+            steps.extend(ops.iter().map(|op| match op.op {
+                SetOp::Keep | SetOp::Drop => set_op(op, ("item", "items")),
+                _ => format!("{} (no effect on a set)", set_op(op, ("item", "items"))),
+            }));
+            steps
+        }
+        Node::Tagged { tag, expr } => {
+            let mut steps = steps(program, *expr);
+            steps.push(format!("mark it as {}", tag));
+            steps
+        }
+        Node::Unary { op: UnaryOp::Neg, expr } => match &program[*expr] {
+            Node::Literal(n) => vec![format!("take -{}", value(*n))],
+            _ => {
+                let mut steps = steps(program, *expr);
+                steps.push("negate it".to_string());
+                steps
+            }
+        },
+        Node::Versus { lhs, rhs } => vec![format!(
+            "compare {} with {}",
+            operand(program, *lhs),
+            operand(program, *rhs),
+        )],
+    }
+}
+
+/// Describes a node that is used by another: numbers and plain dice are named, and anything
+/// else has its steps written out in parentheses.
+fn operand(program: &Program, idx: NodeIdx) -> String {
+    match &program[idx] {
+        Node::Missing => "?".to_string(),
+        Node::Dice { count, sides, ops, .. } if ops.is_empty() => dice(*count, *sides),
+        Node::Literal(n) => value(*n),
+        Node::Tagged { tag, expr } if is_named(&program[*expr]) => {
+            format!("{} marked as {}", operand(program, *expr), tag)
+        }
+        _ => format!("({})", steps(program, idx).join(", ")),
+    }
+}
+
+fn is_named(node: &Node) -> bool {
+    match node {
+        Node::Missing | Node::Literal(_) => true,
+        Node::Dice { ops, .. } => ops.is_empty(),
+        _ => false,
+    }
+}
+
+
+/// Describes a set operation. `one` and `many` name what the operation picks out, like "die" and
+/// "dice".
+fn set_op(op: &SetOperation, (one, many): (&str, &str)) -> String {
+    let SetOperation { op, sel, num } = op;
+
+    // The `h` and `l` selectors pick out a number of dice rather than a value, so they read
+    // differently from the others.
+    let picked = match (sel, num) {
+        (SetSel::Highest, Some(1)) => Some("the highest".to_string()),
+        (SetSel::Highest, _) => Some(format!("the highest {}", number(*num))),
+        (SetSel::Lowest, Some(1)) => Some("the lowest".to_string()),
+        (SetSel::Lowest, _) => Some(format!("the lowest {}", number(*num))),
+        (SetSel::Number, _) | (SetSel::Greater, _) | (SetSel::Less, _) => None,
+    };
+    let num = value(*num);
+
+    let condition = |verb: &str| match sel {
+        SetSel::Greater => format!("{} more than {}", verb, num),
+        SetSel::Less => format!("{} less than {}", verb, num),
+        _ => format!("{} {}", verb, num),
+    };
+
+    match (op, picked) {
+        (SetOp::Keep, Some(picked)) => format!("keep {}", picked),
+        (SetOp::Keep, None) => format!("keep only {} that {}", many, condition("show")),
+        (SetOp::Drop, Some(picked)) => format!("drop {}", picked),
+        (SetOp::Drop, None) => format!("drop any {} that {}", many, condition("show")),
+        (SetOp::Reroll, Some(picked)) => format!("reroll {}", picked),
+        (SetOp::Reroll, None) => {
+            format!("reroll any {} that {} until none do", one, condition("shows"))
+        }
+        (SetOp::RerollOnce, Some(picked)) => format!("reroll {} once", picked),
+        (SetOp::RerollOnce, None) => format!("reroll any {} that {} once", one, condition("shows")),
+        (SetOp::Explode, Some(picked)) | (SetOp::RerollAdd, Some(picked)) => {
+            format!("roll an extra die for {}", picked)
+        }
+        (SetOp::Explode, None) => format!("roll an extra die whenever one {}", condition("shows")),
+        (SetOp::RerollAdd, None) => {
+            format!("roll an extra die for each {} that {}", one, condition("shows"))
+        }
+        (SetOp::Min, _) => format!("count anything below {} as {}", num, num),
+        (SetOp::Max, _) => format!("count anything above {} as {}", num, num),
+        (SetOp::CritSuccess, Some(picked)) => format!("count {} as a critical success", picked),
+        (SetOp::CritSuccess, None) => {
+            format!("count a {} that {} as a critical success", one, condition("shows"))
+        }
+        (SetOp::CritFail, Some(picked)) => format!("count {} as a critical failure", picked),
+        (SetOp::CritFail, None) => {
+            format!("count a {} that {} as a critical failure", one, condition("shows"))
+        }
+    }
+}
+
+fn band(band: &BandArm) -> String {
+    let BandArm { min, max, label } = band;
+
+    match (min, max) {
+        (Some(min), Some(max)) if min == max => format!("{} means {}", min, label),
+        (Some(min), Some(max)) => format!("{} to {} means {}", min, max, label),
+        (Some(min), None) => format!("{} or more means {}", min, label),
+        (None, Some(max)) => format!("{} or less means {}", max, label),
+        (None, None) => format!("anything means {}", label),
+    }
+}
+
+
+/// Names some dice, like "a twenty-sided die" or "four six-sided dice".
+fn dice(count: Option<u64>, sides: Option<u64>) -> String {
+    let sides = format!("{}-sided", number(sides));
+
+    match count {
+        Some(0) => format!("no {} dice", sides),
+        Some(1) if sides.starts_with(&['e', '8'][..]) => format!("an {} die", sides),
+        Some(1) => format!("a {} die", sides),
+        count => format!("{} {} dice", number(count), sides),
+    }
+}
+
+/// Writes a value, like a number to add or compare with, in digits.
+fn value(n: Option<u64>) -> String {
+    n.map_or_else(|| "?".to_string(), |n| n.to_string())
+}
+
+/// Writes a count or a number of sides in words if it is small, and in digits otherwise.
+fn number(n: Option<u64>) -> String {
+    const WORDS: [&str; 21] = [
+        "zero", "one", "two", "three", "four", "five", "six", "seven", "eight", "nine", "ten",
+        "eleven", "twelve", "thirteen", "fourteen", "fifteen", "sixteen", "seventeen", "eighteen",
+        "nineteen", "twenty",
+    ];
+
+    match n {
+        Some(n) if n < WORDS.len() as u64 => WORDS[n as usize].to_string(),
+        Some(n) => n.to_string(),
+        None => "?".to_string(),
+    }
+}
+
+fn list(items: &[String]) -> String {
+    match items {
+        [] => "nothing".to_string(),
+        [item] => item.clone(),
+        [items @ .., last] => format!("{} and {}", items.join(", "), last),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check(input: &str, expected: &str) {
+        let root = ast::Root::cast(parser::parse(input).syntax()).unwrap();
+
+        assert_eq!(explain(root), expected);
+    }
+
+    #[test]
+    fn dice_with_operations() {
+        check("4d6kh3+2", "roll four six-sided dice, keep the highest three, add 2");
+        check("d20", "roll a twenty-sided die");
+        check("1d8", "roll an eight-sided die");
+        check("3d100pl1", "roll three 100-sided dice, drop the lowest");
+        check("3d100p1", "roll three 100-sided dice, drop any dice that show 1");
+        check("2d20kl1", "roll two twenty-sided dice, keep the lowest");
+    }
+
+    #[test]
+    fn rerolls_and_explosions() {
+        check("2d6rr1", "roll two six-sided dice, reroll any die that shows 1 until none do");
+        check("1d10ro<3", "roll a ten-sided die, reroll any die that shows less than 3 once");
+        check("3d6e6", "roll three six-sided dice, roll an extra die whenever one shows 6");
+        check(
+            "2d6ra>4",
+            "roll two six-sided dice, roll an extra die for each die that shows more than 4",
+        );
+    }
+
+    #[test]
+    fn limits_and_crits() {
+        check("4d6mi2ma5", "roll four six-sided dice, count anything below 2 as 2, \
+                            count anything above 5 as 5");
+        check("1d20cs>18", "roll a twenty-sided die, count a die that shows more than 18 as a \
+                            critical success");
+    }
+
+    #[test]
+    fn arithmetic() {
+        check("1d20 - 2 * 3", "roll a twenty-sided die, subtract (take 2, multiply by 3)");
+        check("(1d6 + 1) / 2", "roll a six-sided die, add 1, divide by 2");
+        check("-1d4 + -2", "roll a four-sided die, negate it, subtract 2");
+        check("1d4 - -2", "roll a four-sided die, add 2");
+        check("1d4 * -2", "roll a four-sided die, multiply by (take -2)");
+        check("2d6 + 1d4kh1", "roll two six-sided dice, add (roll a four-sided die, keep the highest)");
+    }
+
+    #[test]
+    fn sets() {
+        check("(1d20, 5)kh1", "take a twenty-sided die and 5, keep the highest");
+        check("(1, 2, 3)k>1", "take 1, 2 and 3, keep only items that show more than 1");
+        check("()", "take nothing");
+        check(
+            "(1, 2)rr1kh1",
+            "take 1 and 2, reroll any item that shows 1 until none do (no effect on a set), \
+             keep the highest",
+        );
+    }
+
+    #[test]
+    fn tags_versus_and_bands() {
+        check("2d6[fire] + 1d4[cold]", "roll two six-sided dice, mark it as fire, \
+                                        add a four-sided die marked as cold");
+        check("1d20 + 5 vs 15", "compare (roll a twenty-sided die, add 5) with 15");
+        check(
+            "2d6 -> {<=6: miss, 7..9: partial, >=10: hit}",
+            "roll two six-sided dice, 6 or less means miss, 7 to 9 means partial, \
+             10 or more means hit",
+        );
+    }
+
+    #[test]
+    fn missing_parts() {
+        check("1d6 +", "roll a six-sided die, add ?");
+    }
+}
@@ -1,32 +1,92 @@
+mod breakdown;
 mod set_ops;
+mod view;
+pub use view::{DieView, NodeView, NodeViewKind};
 
-use super::{Database, ExprIdx, Total};
+use std::cmp::Ordering;
+use text_size::TextRange;
 
+use super::{
+    Database, ExprIdx, RollError, TagTotals, Tags, TiePolicy, Total, VersusOutcome, Winner,
+};
 
-#[derive(Debug, PartialEq)]
+
+/// Caps how many times a repeating operation (`rr`, `e`) is applied, so that one which always
+/// matches, like `1d6e>0`, still terminates.
+pub(super) const MAX_REPEATS: usize = 100;
+
+
+#[derive(Debug)]
 pub struct Expression {
     pub(super) expr: Expr,
     kept: bool,
+    /// Where the expression was written in the source text, if it was.
+    pub(super) range: Option<TextRange>,
+}
+
+/// Expressions are equal if they rolled the same, wherever they were written.
+impl PartialEq for Expression {
+    fn eq(&self, other: &Self) -> bool {
+        self.expr == other.expr && self.kept == other.kept
+    }
 }
 
 impl Expression {
     pub(super) fn new(expr: Expr) -> Self {
         let kept = expr != Expr::Missing;
 
-        Expression { expr, kept }
+        Expression { expr, kept, range: None }
     }
 
     fn drop(&mut self) {
         self.kept = false;
     }
+
+    /// Collects every die that counts towards this expression's total.
+    pub(super) fn kept_dice<'a>(&'a self, db: &'a Database, dice: &mut Vec<&'a Die>) {
+        if !self.kept {
+            return;
+        }
+
+        match &self.expr {
+            Expr::Missing | Expr::Literal(_) => {}
+            Expr::Band(band) => db.get(band.expr).kept_dice(db, dice),
+            Expr::Binary(binary) => {
+                db.get(binary.lhs).kept_dice(db, dice);
+                db.get(binary.rhs).kept_dice(db, dice);
+            }
+            Expr::Dice(inner) => dice.extend(inner.values.iter().filter(|die| die.kept)),
+            Expr::Set(set) => {
+                for idx in set.items.iter() {
+                    db.get(*idx).kept_dice(db, dice);
+                }
+            }
+            Expr::Tagged(tagged) => db.get(tagged.expr).kept_dice(db, dice),
+            Expr::Unary(unary) => db.get(unary.expr).kept_dice(db, dice),
+            Expr::Versus(versus) => {
+                db.get(versus.lhs).kept_dice(db, dice);
+                db.get(versus.rhs).kept_dice(db, dice);
+            }
+        }
+    }
 }
 
 impl Total for Expression {
-    fn total(&self, db: &Database) -> i64 {
+    fn total(&self, db: &Database) -> Result<i64, RollError> {
         if self.kept {
             self.expr.total(db)
         } else {
-            0
+            Ok(0)
+        }
+    }
+}
+
+impl Tags for Expression {
+    fn tags(&self, db: &Database) -> Result<TagTotals, RollError> {
+        if self.kept {
+            self.expr.tags(db)
+        } else {
+            Ok(TagTotals::new())
         }
     }
 }
@@ -35,21 +95,28 @@ impl Total for Expression {
 #[derive(Debug, PartialEq)]
 pub(super) enum Expr {
     Missing,
+    Band(Band),
     Binary(Binary),
     Dice(Dice),
     Literal(Literal),
     Set(Set),
+    Tagged(Tagged),
     Unary(Unary),
+    Versus(Versus),
 }
 
 impl Expr {
+    pub(super) fn band(expr: ExprIdx, bands: Vec<BandArm>) -> Self {
+        Self::Band(Band { expr, bands })
+    }
+
     pub(super) fn binary(op: BinaryOp, lhs: ExprIdx, rhs: ExprIdx) -> Self {
         Self::Binary(Binary { op, lhs, rhs })
     }
 
-    pub(super) fn dice(count: Option<u64>, sides: Option<u64>,
-                       ops: Vec<SetOperation>, db: &mut Database) -> Expr {
-        let dice = Dice::new(count, sides, ops, db);
+    pub(super) fn dice(count: Option<u64>, sides: Option<u64>, ops: Vec<SetOperation>,
+                       range: TextRange, db: &mut Database) -> Expr {
+        let dice = Dice::new(count, sides, ops, range, db);
 
         Self::Dice(dice)
     }
@@ -58,29 +125,97 @@ impl Expr {
         Self::Literal(Literal::new(n))
     }
 
-    pub(super) fn set(items: Vec<ExprIdx>, ops: Vec<SetOperation>) -> Self {
-        Self::Set(Set { items, ops })
+    pub(super) fn set(items: Vec<ExprIdx>, ops: Vec<SetOperation>, db: &mut Database) -> Self {
+        Self::Set(Set::new(items, ops, db))
+    }
+
+    pub(super) fn tagged(tag: String, expr: ExprIdx) -> Self {
+        Self::Tagged(Tagged { tag, expr })
     }
 
     pub(super) fn unary(op: UnaryOp, expr: ExprIdx) -> Self {
         Self::Unary(Unary { op, expr })
     }
+
+    pub(super) fn versus(lhs: ExprIdx, rhs: ExprIdx) -> Self {
+        Self::Versus(Versus { lhs, rhs })
+    }
 }
 
 impl Total for Expr {
-    fn total(&self, db: &Database) -> i64 {
+    fn total(&self, db: &Database) -> Result<i64, RollError> {
         match self {
-            Self::Missing => 0,
+            Self::Missing => Ok(0),
+            Self::Band(band) => band.total(db),
             Self::Binary(binary) => binary.total(db),
             Self::Dice(dice) => dice.total(db),
-            Self::Literal(literal) => literal.total(db),
+            Self::Literal(literal) => Ok(literal.value()),
             Self::Set(set) => set.total(db),
+            Self::Tagged(tagged) => tagged.total(db),
             Self::Unary(unary) => unary.total(db),
+            Self::Versus(versus) => versus.total(db),
+        }
+    }
+}
+
+impl Tags for Expr {
+    fn tags(&self, db: &Database) -> Result<TagTotals, RollError> {
+        match self {
+            Self::Missing | Self::Dice(_) | Self::Literal(_) | Self::Versus(_) => Ok(TagTotals::new()),
+            Self::Band(band) => db.get(band.expr).tags(db),
+            Self::Binary(binary) => binary.tags(db),
+            Self::Set(set) => set.tags(db),
+            Self::Tagged(tagged) => tagged.tags(db),
+            Self::Unary(unary) => unary.tags(db),
         }
     }
 }
 
 
+/// Classifies the total of an expression into the first of its bands that contains it.
+#[derive(Debug, PartialEq)]
+pub(super) struct Band {
+    expr: ExprIdx,
+    bands: Vec<BandArm>,
+}
+
+impl Band {
+    pub(super) fn label(&self, db: &Database) -> Result<Option<&str>, RollError> {
+        let total = self.total(db)?;
+
+        Ok(self.bands
+            .iter()
+            .find(|band| band.contains(total))
+            .map(|band| band.label.as_str()))
+    }
+}
+
+impl Total for Band {
+    fn total(&self, db: &Database) -> Result<i64, RollError> {
+        db.get(self.expr).total(db)
+    }
+}
+
+
+/// A labelled range of totals. Both bounds are inclusive, and a missing bound is unbounded.
+#[derive(Debug, Clone, PartialEq)]
+pub(super) struct BandArm {
+    pub(super) min: Option<i64>,
+    pub(super) max: Option<i64>,
+    pub(super) label: String,
+}
+
+impl BandArm {
+    pub(super) fn new(min: Option<i64>, max: Option<i64>, label: String) -> Self {
+        Self { min, max, label }
+    }
+
+    fn contains(&self, total: i64) -> bool {
+        self.min.is_none_or(|min| min <= total) && self.max.is_none_or(|max| total <= max)
+    }
+}
+
+
 #[derive(Debug, PartialEq)]
 pub(super) struct Binary {
     op: BinaryOp,
@@ -89,22 +224,62 @@ pub(super) struct Binary {
 }
 
 impl Total for Binary {
-    fn total(&self, db: &Database) -> i64 {
+    fn total(&self, db: &Database) -> Result<i64, RollError> {
         let lhs = db.get(self.lhs);
-        let lhs = lhs.total(db);
+        let lhs = lhs.total(db)?;
 
         let rhs = db.get(self.rhs);
-        let rhs = rhs.total(db);
+        let rhs = rhs.total(db)?;
+
+        self.op.apply(lhs, rhs)
+    }
+}
+
+impl Tags for Binary {
+    /// Tagged subtotals are added or subtracted tag by tag. Multiplying or dividing a tagged
+    /// operand by an untagged one scales each of its subtotals; any other product or quotient is
+    /// untagged.
+    fn tags(&self, db: &Database) -> Result<TagTotals, RollError> {
+        let lhs = db.get(self.lhs);
+        let rhs = db.get(self.rhs);
+
+        let (lhs_tags, rhs_tags) = (lhs.tags(db)?, rhs.tags(db)?);
 
         match self.op {
-            BinaryOp::Add => lhs + rhs,
-            BinaryOp::Sub => lhs - rhs,
-            BinaryOp::Mul => lhs * rhs,
-            BinaryOp::Div => lhs / rhs,  // TODO: handle division by 0
+            BinaryOp::Add | BinaryOp::Sub => merge_tags(lhs_tags, rhs_tags, self.op),
+            BinaryOp::Mul if rhs_tags.is_empty() => {
+                let rhs = rhs.total(db)?;
+                map_tags(lhs_tags, |total| self.op.apply(total, rhs))
+            }
+            BinaryOp::Mul if lhs_tags.is_empty() => {
+                let lhs = lhs.total(db)?;
+                map_tags(rhs_tags, |total| self.op.apply(lhs, total))
+            }
+            BinaryOp::Div if rhs_tags.is_empty() => {
+                let rhs = rhs.total(db)?;
+                map_tags(lhs_tags, |total| self.op.apply(total, rhs))
+            }
+            BinaryOp::Mul | BinaryOp::Div => Ok(TagTotals::new()),
         }
     }
 }
 
+/// Adds or subtracts each of `rhs`'s subtotals from the subtotal of the same tag in `lhs`.
+fn merge_tags(mut lhs: TagTotals, rhs: TagTotals, op: BinaryOp) -> Result<TagTotals, RollError> {
+    for (tag, total) in rhs {
+        let subtotal = lhs.entry(tag).or_insert(0);
+        *subtotal = op.apply(*subtotal, total)?;
+    }
+
+    Ok(lhs)
+}
+
+fn map_tags(tags: TagTotals, f: impl Fn(i64) -> Result<i64, RollError>) -> Result<TagTotals, RollError> {
+    tags.into_iter()
+        .map(|(tag, total)| Ok((tag, f(total)?)))
+        .collect()
+}
+
 
 #[derive(Debug, PartialEq)]
 pub(super) struct Dice {
@@ -112,69 +287,163 @@ pub(super) struct Dice {
     sides: Option<u64>,
     values: Vec<Die>,
     ops: Vec<SetOperation>,
+    range: TextRange,
 }
 
 impl Dice {
-    fn new(count: Option<u64>, sides: Option<u64>,
-           ops: Vec<SetOperation>, db: &mut Database) -> Self {
-        if let (Some(sides), Some(count)) = (sides, count) {
-            let values: Vec<_> =
-                db.roll_many(sides, count)
-                    .map(|roll| db.alloc(
-                        Expression::new(
-                            Expr::literal(Some(roll)))))
-                    .map(|idx| Die::new(sides, idx))
-                    .collect();
-
-            Self { count: Some(count), sides: Some(sides), values, ops }
+    /// Rolls the dice and applies `ops` to them.
+    ///
+    /// A count of zero is rolled by the context's `ZeroDiceRule`, which keeps or drops its dice
+    /// before any of `ops` are applied.
+    fn new(count: Option<u64>, sides: Option<u64>, ops: Vec<SetOperation>,
+           range: TextRange, db: &mut Database) -> Self {
+        let mut dice = if let (Some(sides), Some(0)) = (sides, count) {
+            let rule = db.ctx.zero_dice.clone();
+            let mut pool = ZeroDicePool { sides, range, values: Vec::new(), db };
+            rule.roll(&mut pool);
+
+            Self { count, sides: Some(sides), values: pool.values, ops: Vec::new(), range }
+        } else if let (Some(sides), Some(count)) = (sides, count) {
+            let values: Vec<_> = (0..count)
+                .map(|_| Die::roll_new(sides, range, db))
+                .collect();
+
+            Self { count: Some(count), sides: Some(sides), values, ops: Vec::new(), range }
         } else {
-            Self { count, sides, values: Vec::new(), ops }  // TODO: Passing the buck
+            Self { count, sides, values: Vec::new(), ops: Vec::new(), range }  // TODO: Passing the buck
+        };
+
+        // Operations with a number that is missing or too large to parse have already been
+        // reported by the parser or validation.
+        for op in ops.iter().filter(|op| op.num.is_some()) {
+            set_ops::operate_on_dice(op, &mut dice, db);
+        }
+
+        dice.ops = ops;
+        dice.flag_crits(db);
+
+        dice
+    }
+
+    /// Flags each die whose final value is in the critical success or failure range.
+    ///
+    /// Without an explicit `cs` or `cf` operation, only a d20 crits, on a natural 20, and
+    /// fumbles, on a natural 1. Other dice are never flagged unless the expression asks for it.
+    fn flag_crits(&mut self, db: &Database) {
+        let in_range = |op: SetOp, value: i64, default: Option<i64>| {
+            let mut ops = self.ops.iter().filter(|o| o.op == op).peekable();
+
+            if ops.peek().is_none() {
+                default == Some(value)
+            } else {
+                ops.any(|o| o.matches(value))
+            }
+        };
+
+        let flags: Vec<_> = self.values
+            .iter()
+            .map(|die| {
+                let value = die.value(db);
+                let d20 = |face| Some(face).filter(|_| die.sides == 20);
+
+                (in_range(SetOp::CritSuccess, value, d20(20)),
+                 in_range(SetOp::CritFail, value, d20(1)))
+            })
+            .collect();
+
+        for (die, (crit, fumble)) in self.values.iter_mut().zip(flags) {
+            die.crit = crit;
+            die.fumble = fumble;
         }
     }
 
     fn roll_another(&mut self, db: &mut Database) {
-        let die = Die::roll_new(self.sides.unwrap(), db);  // TODO
+        let die = Die::roll_new(self.sides.unwrap(), self.range, db);  // TODO
 
         self.values.push(die);
     }
 }
 
 impl Total for Dice {
-    fn total(&self, db: &Database) -> i64 {
+    fn total(&self, db: &Database) -> Result<i64, RollError> {
         self.values
             .iter()
-            .map(|die| die.total(db))
-            .sum()
+            .filter(|die| die.kept)
+            .try_fold(0, |total, die| BinaryOp::Add.apply(total, die.value(db)))
+    }
+}
+
+
+/// A pool of zero dice, being rolled by a `ZeroDiceRule`.
+pub struct ZeroDicePool<'a> {
+    sides: u64,
+    range: TextRange,
+    values: Vec<Die>,
+    db: &'a mut Database,
+}
+
+impl ZeroDicePool<'_> {
+    /// The number of sides of the dice the pool was written with, like 6 for `0d6`.
+    pub fn sides(&self) -> u64 {
+        self.sides
+    }
+
+    /// Rolls another die into the pool, returning what it shows.
+    pub fn roll(&mut self) -> u64 {
+        let die = Die::roll_new(self.sides, self.range, self.db);
+        let value = die.value(self.db) as u64;
+        self.values.push(die);
+
+        value
+    }
+
+    /// Stops the `i`th die rolled into the pool from counting towards its total.
+    ///
+    /// Panics if fewer than `i + 1` dice have been rolled.
+    pub fn drop(&mut self, i: usize) {
+        self.values[i].drop();
     }
 }
 
 
 #[derive(Debug, PartialEq)]
 pub(super) struct Die {
+    /// Numbers the dice of a roll in the order they were first rolled, for its log.
+    id: usize,
     sides: u64,
+    range: TextRange,
     values: Vec<ExprIdx>,
     kept: bool,
+    crit: bool,
+    fumble: bool,
 }
 
 impl Die {
-    fn new(sides: u64, value: ExprIdx) -> Self {
-        Self { sides, values: vec![value], kept: true }
+    fn roll_new(sides: u64, range: TextRange, db: &mut Database) -> Self {
+        let id = db.next_die();
+
+        let mut die = Self {
+            id, sides, range, values: Vec::new(), kept: true, crit: false, fumble: false,
+        };
+        die.add_roll(db);
+
+        die
     }
 
-    fn roll_new(sides: u64, db: &mut Database) -> Self {
-        let value = Expr::literal(Some(db.roll(sides)));
-        let value = db.alloc(Expression::new(value));
+    pub(super) fn is_crit(&self) -> bool {
+        self.crit
+    }
 
-        Self { sides, values: vec![value], kept: true }
+    pub(super) fn is_fumble(&self) -> bool {
+        self.fumble
     }
 
     fn drop(&mut self) {
         self.kept = false;
     }
 
+    /// Rolls the die again. Earlier values are kept in `values` as the die's reroll history.
     fn reroll(&mut self, db: &mut Database) {
-        self.values.pop();
-
         self.add_roll(db);
     }
 
@@ -199,20 +468,21 @@ impl Die {
     }
 
     fn add_roll(&mut self, db: &mut Database) {
-        let roll = Expression::new(Expr::literal(Some(db.roll(self.sides))));
+        let roll = Expression::new(Expr::literal(Some(db.roll(self.sides, self.id, self.range))));
         let roll = db.alloc(roll);
 
         self.values.push(roll);
     }
 }
 
-impl Total for Die {
-    fn total(&self, db: &Database) -> i64 {
+impl Die {
+    /// The value the die settled on.
+    pub(super) fn value(&self, db: &Database) -> i64 {
         let expr = self.values.last()
             .map(|idx| db.get(*idx));
 
         if let Some(Expression { expr: Expr::Literal(literal), .. }) = expr {
-            literal.total(db)
+            literal.value()
         } else if expr.is_some() {
             unreachable!()
         } else {
@@ -230,7 +500,7 @@ pub(super) struct Literal {
 
 impl Literal {
     fn new(n: Option<u64>) -> Self {
-        let values = n.map(|n| vec![n]).unwrap_or(Vec::new());
+        let values = n.map(|n| vec![n]).unwrap_or_default();
         Self { values, exploded: false }
     }
 
@@ -241,10 +511,8 @@ impl Literal {
     fn update(&mut self, value: u64) {
         self.values.push(value);
     }
-}
 
-impl Total for Literal {
-    fn total(&self, _db: &Database) -> i64 {
+    pub(super) fn value(&self) -> i64 {
         self.values.last().map(|&x| x as i64).unwrap_or(0)  // TODO: handle missing
     }
 }
@@ -256,13 +524,58 @@ pub(super) struct Set {
     ops: Vec<SetOperation>,
 }
 
+impl Set {
+    fn new(items: Vec<ExprIdx>, ops: Vec<SetOperation>, db: &mut Database) -> Self {
+        let mut set = Self { items, ops: Vec::new() };
+
+        for op in ops.iter().filter(|op| op.num.is_some()) {
+            set_ops::operate_on_set(op, &mut set, db);
+        }
+
+        set.ops = ops;
+
+        set
+    }
+}
+
 impl Total for Set {
-    fn total(&self, db: &Database) -> i64 {
+    fn total(&self, db: &Database) -> Result<i64, RollError> {
         self.items
             .iter()
             .map(|idx| db.get(*idx))
-            .map(|expr| expr.total(db))
-            .sum()
+            .try_fold(0, |total, expr| BinaryOp::Add.apply(total, expr.total(db)?))
+    }
+}
+
+impl Tags for Set {
+    fn tags(&self, db: &Database) -> Result<TagTotals, RollError> {
+        self.items
+            .iter()
+            .map(|idx| db.get(*idx).tags(db))
+            .try_fold(TagTotals::new(), |acc, tags| merge_tags(acc, tags?, BinaryOp::Add))
+    }
+}
+
+
+#[derive(Debug, PartialEq)]
+pub(super) struct Tagged {
+    tag: String,
+    expr: ExprIdx,
+}
+
+impl Total for Tagged {
+    fn total(&self, db: &Database) -> Result<i64, RollError> {
+        db.get(self.expr).total(db)
+    }
+}
+
+impl Tags for Tagged {
+    /// A tag applies to the whole of its operand, replacing any tags inside it.
+    fn tags(&self, db: &Database) -> Result<TagTotals, RollError> {
+        let mut tags = TagTotals::new();
+        tags.insert(self.tag.clone(), self.total(db)?);
+
+        Ok(tags)
     }
 }
 
@@ -274,17 +587,66 @@ pub(super) struct Unary {
 }
 
 impl Total for Unary {
-    fn total(&self, db: &Database) -> i64 {
+    fn total(&self, db: &Database) -> Result<i64, RollError> {
         let expr = db.get(self.expr);
-        let total = expr.total(db);
+        let total = expr.total(db)?;
+
+        match self.op {
+            UnaryOp::Neg => total.checked_neg().ok_or(RollError::Overflow),
+        }
+    }
+}
+
+impl Tags for Unary {
+    fn tags(&self, db: &Database) -> Result<TagTotals, RollError> {
+        let tags = db.get(self.expr).tags(db)?;
 
         match self.op {
-            UnaryOp::Neg => -total,
+            UnaryOp::Neg => map_tags(tags, |total| total.checked_neg().ok_or(RollError::Overflow)),
         }
     }
 }
 
 
+#[derive(Debug, PartialEq)]
+pub(super) struct Versus {
+    lhs: ExprIdx,
+    rhs: ExprIdx,
+}
+
+impl Versus {
+    pub(super) fn outcome(&self, db: &Database, tie_policy: TiePolicy) -> Result<VersusOutcome, RollError> {
+        let lhs = db.get(self.lhs).total(db)?;
+        let rhs = db.get(self.rhs).total(db)?;
+
+        let winner = match lhs.cmp(&rhs) {
+            Ordering::Greater => Winner::Lhs,
+            Ordering::Less => Winner::Rhs,
+            Ordering::Equal => match tie_policy {
+                TiePolicy::Tie => Winner::Tie,
+                TiePolicy::LhsWins => Winner::Lhs,
+                TiePolicy::RhsWins => Winner::Rhs,
+            },
+        };
+
+        Ok(VersusOutcome {
+            lhs,
+            rhs,
+            winner,
+            margin: lhs.abs_diff(rhs),
+        })
+    }
+}
+
+impl Total for Versus {
+    /// The margin by which the left-hand side beat the right-hand side, which is negative if it
+    /// lost.
+    fn total(&self, db: &Database) -> Result<i64, RollError> {
+        BinaryOp::Sub.apply(db.get(self.lhs).total(db)?, db.get(self.rhs).total(db)?)
+    }
+}
+
+
 #[derive(Debug, Copy, Clone, PartialEq)]
 pub(super) enum BinaryOp {
     Add,
@@ -293,6 +655,28 @@ pub(super) enum BinaryOp {
     Div,
 }
 
+impl BinaryOp {
+    /// Works out `lhs op rhs`, or fails if it divides by zero or is too large for an `i64`.
+    pub(super) fn apply(self, lhs: i64, rhs: i64) -> Result<i64, RollError> {
+        match self {
+            Self::Add => lhs.checked_add(rhs).ok_or(RollError::Overflow),
+            Self::Sub => lhs.checked_sub(rhs).ok_or(RollError::Overflow),
+            Self::Mul => lhs.checked_mul(rhs).ok_or(RollError::Overflow),
+            Self::Div if rhs == 0 => Err(RollError::DivisionByZero),
+            Self::Div => lhs.checked_div(rhs).ok_or(RollError::Overflow),
+        }
+    }
+
+    fn symbol(self) -> &'static str {
+        match self {
+            Self::Add => "+",
+            Self::Sub => "-",
+            Self::Mul => "*",
+            Self::Div => "/",
+        }
+    }
+}
+
 
 #[derive(Debug, Copy, Clone, PartialEq)]
 pub(super) enum UnaryOp {
@@ -300,49 +684,32 @@ pub(super) enum UnaryOp {
 }
 
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub(super) struct SetOperation {
-    op: SetOp,
-    sel: SetSel,
-    num: Option<u64>,
+    pub(super) op: SetOp,
+    pub(super) sel: SetSel,
+    pub(super) num: Option<u64>,
 }
 
 impl SetOperation {
-    const INCOMPATIBLE_SET_OPS: &'static [(SetOp, &'static [SetSel])] = &[
-        (SetOp::Reroll, &[SetSel::Highest, SetSel::Lowest]),
-        (SetOp::Min, &[SetSel::Lowest, SetSel::Highest, SetSel::Greater, SetSel::Less]),
-        (SetOp::Max, &[SetSel::Lowest, SetSel::Highest, SetSel::Greater, SetSel::Less]),
-    ];
-
     pub(super) fn new(op: SetOp, sel: SetSel, num: Option<u64>) -> Self {
         Self { op, sel, num }
     }
 
-    fn operate(&self, target: &mut Expression, db: &mut Database) {
-        match target {
-            Expression { expr: Expr::Dice(dice), .. } => {
-                set_ops::operate_on_dice(self, dice, db)
-            }
-            Expression { expr: Expr::Set(set), .. } => {
-                set_ops::operate_on_set(self, set, db)
-            }
-            _ => unreachable!(),
-        }
-    }
+    /// Whether `value` is picked out by this operation's selector. The `h` and `l` selectors
+    /// depend on the other values in the pool, so they never match a lone value.
+    pub(super) fn matches(&self, value: i64) -> bool {
+        let num = match self.num {
+            Some(num) => num as i64,
+            None => return false,
+        };
 
-    fn validate(&self) -> bool {
-        for (op, sels) in Self::INCOMPATIBLE_SET_OPS.iter() {
-            if self.op != *op {
-                continue
-            }
-            for sel in sels.iter() {
-                if self.sel == *sel {
-                    return false;
-                }
-            }
+        match self.sel {
+            SetSel::Number => value == num,
+            SetSel::Greater => value > num,
+            SetSel::Less => value < num,
+            SetSel::Highest | SetSel::Lowest => false,
         }
-
-        true
     }
 }
 
@@ -357,6 +724,8 @@ pub(super) enum SetOp {
     Explode,
     Min,
     Max,
+    CritSuccess,
+    CritFail,
 }
 
 
@@ -373,7 +742,7 @@ pub(super) enum SetSel {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::{SEED, RollContext};
+    use crate::{FixedRolls, Program, SEED, RollContext, ZeroDicePolicy, ZeroDicePool};
     use rand::prelude::*;
 
     fn parse(input: &str) -> ast::Root {
@@ -384,16 +753,21 @@ mod tests {
         StdRng::seed_from_u64(SEED).sample(rand::distributions::Uniform::new_inclusive(1, sides)) as i64
     }
 
+    /// Rolls one die for each entry of `sides`, in order, from a single seeded stream.
+    fn rolls(sides: &[u64]) -> Vec<i64> {
+        let mut rng = StdRng::seed_from_u64(SEED);
+
+        sides.iter()
+            .map(|sides| rng.sample(rand::distributions::Uniform::new_inclusive(1, *sides)) as i64)
+            .collect()
+    }
+
     fn check(input: &str, expected_total: i64) {
-        let ast = parse(input);
-        let expr = ast.expr();
-        let mut db = Database {
-            exprs: la_arena::Arena::new(),
-            ctx: RollContext::new(StdRng::seed_from_u64(SEED)),
-        };
-        let hir = db.lower_expr(expr);
+        let program = Program::compile(parse(input));
+        let mut db = Database::new(RollContext::new(StdRng::seed_from_u64(SEED)));
+        let hir = db.lower(&program);
 
-        assert_eq!(hir.total(&mut db), expected_total);
+        assert_eq!(hir.total(&db), Ok(expected_total));
     }
 
     #[test]
@@ -428,6 +802,382 @@ mod tests {
         check("5 * 1d8 - 6", total);
     }
 
+    /// The values of every kept die, in the order they were first rolled.
+    fn check_die_values(input: &str, expected_values: Vec<i64>) {
+        let program = Program::compile(parse(input));
+        let mut db = Database::new(RollContext::new(StdRng::seed_from_u64(SEED)));
+        let hir = db.lower(&program);
+
+        let mut dice = Vec::new();
+        hir.kept_dice(&db, &mut dice);
+
+        let values: Vec<_> = dice.iter().map(|die| die.value(&db)).collect();
+
+        assert_eq!(values, expected_values);
+    }
+
+    fn check_with(input: &str, ctx: RollContext, expected_total: i64) {
+        let program = Program::compile(parse(input));
+        let mut db = Database::new(ctx);
+        let hir = db.lower(&program);
+
+        assert_eq!(hir.total(&db), Ok(expected_total));
+    }
+
+    fn check_crits(input: &str, expected_crit: bool, expected_fumble: bool) {
+        let ctx = RollContext::new(StdRng::seed_from_u64(SEED));
+
+        check_crits_with(input, ctx, expected_crit, expected_fumble);
+    }
+
+    fn check_crits_with(input: &str, ctx: RollContext, expected_crit: bool, expected_fumble: bool) {
+        let program = Program::compile(parse(input));
+        let mut db = Database::new(ctx);
+        let hir = db.lower(&program);
+
+        let mut dice = Vec::new();
+        hir.kept_dice(&db, &mut dice);
+
+        assert_eq!(dice.iter().any(|die| die.is_crit()), expected_crit);
+        assert_eq!(dice.iter().any(|die| die.is_fumble()), expected_fumble);
+    }
+
+    fn check_tags(input: &str, expected_tags: &[(&str, i64)]) {
+        let program = Program::compile(parse(input));
+        let mut db = Database::new(RollContext::new(StdRng::seed_from_u64(SEED)));
+        let hir = db.lower(&program);
+
+        let expected_tags: TagTotals = expected_tags
+            .iter()
+            .map(|(tag, total)| (tag.to_string(), *total))
+            .collect();
+
+        assert_eq!(hir.tags(&db), Ok(expected_tags));
+    }
+
+    fn check_error(input: &str, expected_error: RollError) {
+        let program = Program::compile(parse(input));
+        let mut db = Database::new(RollContext::new(StdRng::seed_from_u64(SEED)));
+        let hir = db.lower(&program);
+
+        assert_eq!(hir.total(&db), Err(expected_error));
+    }
+
+    #[test]
+    fn division_by_zero_is_an_error() {
+        check_error("1d6 / 0", RollError::DivisionByZero);
+        check_error("1d6 / (1d1 - 1) + 2", RollError::DivisionByZero);
+        check_error("1 / 0 vs 2", RollError::DivisionByZero);
+    }
+
+    #[test]
+    fn overflow_is_an_error() {
+        check_error("9223372036854775807 + 1", RollError::Overflow);
+        check_error("3037000500 * 3037000500", RollError::Overflow);
+        check_error("-(0 - 9223372036854775807 - 1)", RollError::Overflow);
+        check_error("(0 - 9223372036854775807 - 1) / -1", RollError::Overflow);
+        check_error("(9223372036854775807, 1)", RollError::Overflow);
+    }
+
+    #[test]
+    fn dropped_items_that_fail_do_not_count() {
+        check("(1 / 0, 5)kh1", 5);
+        check_error("(1 / 0, 5)kl1", RollError::DivisionByZero);
+    }
+
+    #[test]
+    fn total_tagged() {
+        check("1d6[fire] + 3[cold]", roll(6) + 3);
+    }
+
+    #[test]
+    fn tags_are_summed() {
+        let rolls = rolls(&[6, 4]);
+
+        check_tags("1d6[fire] + 1d4[cold] + 3[fire]", &[
+            ("cold", rolls[1]),
+            ("fire", rolls[0] + 3),
+        ]);
+    }
+
+    #[test]
+    fn tags_are_subtracted() {
+        check_tags("10[fire] - 4[fire] - 2[cold]", &[("cold", -2), ("fire", 6)]);
+    }
+
+    #[test]
+    fn untagged_operands_have_no_tags() {
+        check_tags("1d6[fire] + 2", &[("fire", roll(6))]);
+    }
+
+    #[test]
+    fn outer_tag_replaces_inner_tags() {
+        check_tags("(5[fire] + 2)[cold]", &[("cold", 7)]);
+    }
+
+    #[test]
+    fn tags_are_scaled_by_untagged_operands() {
+        check_tags("(6[fire] + 4[cold]) * 3 / 2", &[("cold", 6), ("fire", 9)]);
+    }
+
+    #[test]
+    fn product_of_tagged_operands_is_untagged() {
+        check_tags("2[fire] * 3[cold]", &[]);
+    }
+
+    #[test]
+    fn tags_that_divide_by_zero_are_an_error() {
+        let program = Program::compile(parse("2d6[fire] / 0"));
+        let mut db = Database::new(RollContext::new(StdRng::seed_from_u64(SEED)));
+        let hir = db.lower(&program);
+
+        assert_eq!(hir.tags(&db), Err(RollError::DivisionByZero));
+    }
+
+    #[test]
+    fn tags_that_overflow_are_an_error() {
+        let program = Program::compile(parse("9223372036854775807[fire] + 1[fire]"));
+        let mut db = Database::new(RollContext::new(StdRng::seed_from_u64(SEED)));
+        let hir = db.lower(&program);
+
+        assert_eq!(hir.tags(&db), Err(RollError::Overflow));
+    }
+
+    #[test]
+    fn missing_operands_that_divide_by_zero_are_an_error() {
+        let result = crate::roll(parse("-/3d%/"));
+
+        assert_eq!(result.tagged_total(), Err(RollError::DivisionByZero));
+        assert_eq!(result.breakdown().total, Err(RollError::DivisionByZero));
+        assert_eq!(result.record().total, None);
+    }
+
+    #[test]
+    fn tags_are_negated() {
+        check_tags("-3[fire]", &[("fire", -3)]);
+    }
+
+    #[test]
+    fn total_versus() {
+        check("1d20 + 5 vs 12", roll(20) + 5 - 12);
+    }
+
+    #[test]
+    fn versus_outcome() {
+        let program = Program::compile(parse("1d20 + 10 vs 14"));
+        let mut db = Database::new(RollContext::new(StdRng::seed_from_u64(SEED)));
+        let hir = db.lower(&program);
+
+        if let Expr::Versus(versus) = &hir.expr {
+            assert_eq!(versus.outcome(&db, TiePolicy::Tie), Ok(VersusOutcome {
+                lhs: roll(20) + 10,
+                rhs: 14,
+                winner: Winner::Lhs,
+                margin: (roll(20) + 10 - 14) as u64,
+            }));
+        } else {
+            panic!()
+        }
+    }
+
+    #[test]
+    fn versus_tie_policies() {
+        let mut db = Database::default();
+        let lhs = db.alloc(Expression::new(Expr::literal(Some(7))));
+        let rhs = db.alloc(Expression::new(Expr::literal(Some(7))));
+        let versus = Versus { lhs, rhs };
+
+        let winner = |tie_policy| versus.outcome(&db, tie_policy).unwrap().winner;
+
+        assert_eq!(winner(TiePolicy::Tie), Winner::Tie);
+        assert_eq!(winner(TiePolicy::LhsWins), Winner::Lhs);
+        assert_eq!(winner(TiePolicy::RhsWins), Winner::Rhs);
+    }
+
+    fn check_band(input: &str, expected_label: Option<&str>) {
+        let program = Program::compile(parse(input));
+        let mut db = Database::new(RollContext::new(StdRng::seed_from_u64(SEED)));
+        let hir = db.lower(&program);
+
+        if let Expr::Band(band) = &hir.expr {
+            assert_eq!(band.label(&db), Ok(expected_label));
+        } else {
+            panic!()
+        }
+    }
+
+    #[test]
+    fn total_band() {
+        check("1d20 + 2 -> {<=10: miss, >10: hit}", roll(20) + 2);
+    }
+
+    #[test]
+    fn band_label_below() {
+        check_band("5 -> {<=6: miss, 7..9: partial, >=10: hit}", Some("miss"));
+    }
+
+    #[test]
+    fn band_label_range() {
+        check_band("2 + 7 -> {<=6: miss, 7..9: partial, >=10: hit}", Some("partial"));
+    }
+
+    #[test]
+    fn band_label_above() {
+        check_band("10 -> {<=6: miss, 7..9: partial, >=10: hit}", Some("hit"));
+    }
+
+    #[test]
+    fn band_first_match_wins() {
+        check_band("20 -> {20: crit, >=15: hit}", Some("crit"));
+    }
+
+    #[test]
+    fn band_without_match() {
+        check_band("8 -> {<8: low, >8: high}", None);
+    }
+
+    #[test]
+    fn bands_at_the_limits_of_i64() {
+        check_band("1 -> {>9223372036854775807: x}", None);
+        check_band("9223372036854775807 -> {>=9223372036854775807: max}", Some("max"));
+        check_band("1 -> {9223372036854775808: x, >18446744073709551615: y, >=0: z}", Some("z"));
+        check_band("1 -> {<18446744073709551615: x}", Some("x"));
+        check_band("1 -> {<=9223372036854775808: x}", Some("x"));
+        check_band("1 -> {0..18446744073709551615: x}", Some("x"));
+        check_band("1 -> {<0: x, 9223372036854775808..18446744073709551615: y}", None);
+    }
+
+    #[test]
+    fn total_zero_dice() {
+        check("0d6 + 1", 1);
+    }
+
+    #[test]
+    fn total_zero_dice_keep_lowest() {
+        let lowest = StdRng::seed_from_u64(SEED)
+            .sample_iter(rand::distributions::Uniform::new_inclusive(1, 6))
+            .take(2)
+            .min()
+            .unwrap() as i64;
+
+        let ctx = RollContext::new(StdRng::seed_from_u64(SEED))
+            .with_zero_dice(ZeroDicePolicy::KeepLowest(2));
+
+        check_with("0d6", ctx, lowest);
+    }
+
+    #[test]
+    fn zero_dice_with_a_custom_rule() {
+        // Roll two dice and keep the highest, and only if it is a 6.
+        let ctx = RollContext::new(FixedRolls::new(vec![6, 3, 2, 5]))
+            .with_zero_dice(|pool: &mut ZeroDicePool<'_>| {
+                let (first, second) = (pool.roll(), pool.roll());
+                pool.drop(if first < second { 0 } else { 1 });
+
+                if first.max(second) < 6 {
+                    pool.drop(0);
+                    pool.drop(1);
+                }
+            });
+
+        check_with("0d6 + 0d6", ctx, 6);
+    }
+
+    #[test]
+    fn zero_dice_policy_only_applies_to_zero_dice() {
+        let ctx = RollContext::new(StdRng::seed_from_u64(SEED))
+            .with_zero_dice(ZeroDicePolicy::KeepLowest(2));
+
+        check_with("1d20", ctx, roll(20));
+    }
+
+    #[test]
+    fn dice_in_a_pool_draw_from_one_stream() {
+        check_die_values("8d20", rolls(&[20; 8]));
+    }
+
+    #[test]
+    fn consecutive_pools_draw_from_one_stream() {
+        check_die_values("4d20 + 4d20", rolls(&[20; 8]));
+    }
+
+    #[test]
+    fn pools_in_a_set_draw_from_one_stream() {
+        check_die_values("(2d6, 2d6)", rolls(&[6; 4]));
+    }
+
+    #[test]
+    fn set_items_draw_from_one_stream() {
+        check_die_values("(1d20, 1d8, 1d20)", rolls(&[20, 8, 20]));
+    }
+
+    #[test]
+    fn rerolls_draw_from_one_stream() {
+        // [5, 15, 16, 17]
+        let rolls = rolls(&[20; 4]);
+
+        check_die_values("2d20ro<10 + 1d20", vec![rolls[2], rolls[1], rolls[3]]);
+    }
+
+    #[test]
+    fn explosions_draw_from_one_stream() {
+        // [5, 15, 16, 17, 5, 5]
+        check_die_values("2d20e>10 + 1d20", rolls(&[20; 6]));
+    }
+
+    #[test]
+    fn total_excludes_dropped_dice() {
+        // [5, 15]
+        check("2d20kh1", 15);
+    }
+
+    #[test]
+    fn total_excludes_dropped_items() {
+        check("(1, 10, 3)kh2", 13);
+    }
+
+    #[test]
+    fn comparison_selectors_match_every_die() {
+        // [5, 15]
+        check("2d20k>1", 20);
+    }
+
+    #[test]
+    fn d20s_crit_on_a_natural_twenty_and_fumble_on_a_one_by_default() {
+        let rolls = |rolls: &[u64]| RollContext::new(FixedRolls::new(rolls.to_vec()));
+
+        check_crits_with("1d20", rolls(&[20]), true, false);
+        check_crits_with("1d20", rolls(&[1]), false, true);
+        check_crits_with("2d20", rolls(&[19, 2]), false, false);
+    }
+
+    #[test]
+    fn other_dice_only_crit_when_asked_to() {
+        let rolls = |rolls: &[u64]| RollContext::new(FixedRolls::new(rolls.to_vec()));
+
+        check_crits_with("4d6", rolls(&[6, 1, 6, 3]), false, false);
+        check_crits_with("1d1", rolls(&[1]), false, false);
+        check_crits_with("4d6cs6cf1", rolls(&[6, 1, 6, 3]), true, true);
+    }
+
+    #[test]
+    fn explicit_crit_success_range() {
+        check_crits("1d20cs>4", true, false);
+        check_crits("1d20cs>5", false, false);
+    }
+
+    #[test]
+    fn explicit_crit_fail_range() {
+        check_crits("1d20cf5", false, true);
+        check_crits("1d20cf<5", false, false);
+    }
+
+    #[test]
+    fn dropped_dice_are_not_crits() {
+        check_crits("2d20cs>10cf<10kh1", true, false);
+        check_crits("2d20cs>10cf<10kl1", false, true);
+    }
+
     #[test]
     fn rng_is_deterministic() {
         let rng1 = StdRng::seed_from_u64(SEED);
@@ -444,14 +1194,10 @@ mod tests {
     }
 
     #[test]
-    fn catches_invalid_set_operations() {
-        let op = SetOperation::new(SetOp::Reroll, SetSel::Highest, Some(2));
-        assert!(!op.validate());
-    }
-
-    #[test]
-    fn allows_valid_set_operations() {
-        let op = SetOperation::new(SetOp::Keep, SetSel::Highest, Some(5));
-        assert!(op.validate());
+    fn set_operation_matches_selector() {
+        assert!(SetOperation::new(SetOp::CritSuccess, SetSel::Greater, Some(18)).matches(19));
+        assert!(!SetOperation::new(SetOp::CritSuccess, SetSel::Greater, Some(19)).matches(19));
+        assert!(SetOperation::new(SetOp::CritFail, SetSel::Number, Some(1)).matches(1));
+        assert!(!SetOperation::new(SetOp::CritFail, SetSel::Lowest, Some(1)).matches(1));
     }
 }
\ No newline at end of file
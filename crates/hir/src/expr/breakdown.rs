@@ -0,0 +1,269 @@
+use super::*;
+use crate::{DieRoll, Part};
+use std::fmt::Write;
+
+
+/// How tightly each kind of expression binds, as in the parser. An operand that binds less
+/// tightly than its position needs is wrapped in parentheses.
+const VERSUS: u8 = 1;
+const SUM: u8 = 3;
+const PRODUCT: u8 = 5;
+const PREFIX: u8 = 7;
+const ATOM: u8 = 9;
+
+
+impl Expression {
+    /// Breaks the expression down into the pieces it was written with, with the roll of each die
+    /// after its dice. Parentheses are only added where they are needed.
+    pub(in crate) fn breakdown(&self, db: &Database) -> Vec<Part> {
+        let mut parts = Vec::new();
+        self.write_breakdown(db, 0, &mut parts);
+
+        parts
+    }
+
+    fn write_breakdown(&self, db: &Database, min_power: u8, parts: &mut Vec<Part>) {
+        // Missing expressions are never kept, but they weren't dropped either.
+        if self.kept || self.expr == Expr::Missing {
+            self.expr.write_breakdown(db, min_power, parts);
+        } else {
+            let mut dropped = Vec::new();
+            self.expr.write_breakdown(db, min_power, &mut dropped);
+
+            parts.push(Part::Dropped(dropped));
+        }
+    }
+}
+
+impl Expr {
+    fn binding_power(&self, db: &Database) -> u8 {
+        match self {
+            Self::Band(band) => db.get(band.expr).expr.binding_power(db),
+            Self::Binary(Binary { op: BinaryOp::Add | BinaryOp::Sub, .. }) => SUM,
+            Self::Binary(Binary { op: BinaryOp::Mul | BinaryOp::Div, .. }) => PRODUCT,
+            Self::Unary(_) => PREFIX,
+            Self::Versus(_) => VERSUS,
+            Self::Missing | Self::Dice(_) | Self::Literal(_) | Self::Set(_) | Self::Tagged(_) => ATOM,
+        }
+    }
+
+    fn write_breakdown(&self, db: &Database, min_power: u8, parts: &mut Vec<Part>) {
+        let parens = self.binding_power(db) < min_power;
+
+        if parens {
+            push_text(parts, "(");
+        }
+
+        match self {
+            Self::Missing => push_text(parts, "?"),
+            // Bands only label the total, so the banded expression is all there is to show.
+            Self::Band(band) => db.get(band.expr).write_breakdown(db, 0, parts),
+            Self::Binary(binary) => {
+                let power = self.binding_power(db);
+
+                db.get(binary.lhs).write_breakdown(db, power, parts);
+                push_text(parts, &format!(" {} ", binary.op.symbol()));
+                db.get(binary.rhs).write_breakdown(db, power + 1, parts);
+            }
+            Self::Dice(dice) => dice.write_breakdown(db, parts),
+            Self::Literal(literal) => match literal.values.last() {
+                Some(value) => push_text(parts, &value.to_string()),
+                None => push_text(parts, "?"),
+            },
+            Self::Set(set) => {
+                push_text(parts, "(");
+
+                for (i, idx) in set.items.iter().enumerate() {
+                    if i > 0 {
+                        push_text(parts, ", ");
+                    }
+
+                    db.get(*idx).write_breakdown(db, 0, parts);
+                }
+
+                // A set of one item is written with a trailing comma, to tell it apart from
+                // parentheses.
+                if set.items.len() == 1 {
+                    push_text(parts, ",");
+                }
+
+                push_text(parts, ")");
+                push_text(parts, &ops_text(&set.ops));
+            }
+            Self::Tagged(tagged) => {
+                db.get(tagged.expr).write_breakdown(db, ATOM, parts);
+                push_text(parts, &format!("[{}]", tagged.tag));
+            }
+            Self::Unary(unary) => {
+                match unary.op {
+                    UnaryOp::Neg => push_text(parts, "-"),
+                }
+
+                db.get(unary.expr).write_breakdown(db, PREFIX, parts);
+            }
+            Self::Versus(versus) => {
+                db.get(versus.lhs).write_breakdown(db, VERSUS, parts);
+                push_text(parts, " vs ");
+                db.get(versus.rhs).write_breakdown(db, VERSUS + 1, parts);
+            }
+        }
+
+        if parens {
+            push_text(parts, ")");
+        }
+    }
+}
+
+impl Dice {
+    /// Writes the dice, like `4d6kh3`, followed by every roll of every die.
+    fn write_breakdown(&self, db: &Database, parts: &mut Vec<Part>) {
+        let number = |n: Option<u64>| n.map_or("?".to_string(), |n| n.to_string());
+
+        let dice = format!("{}d{}{}", number(self.count), number(self.sides), ops_text(&self.ops));
+
+        push_text(parts, &dice);
+        push_text(parts, " (");
+
+        for (i, roll) in self.values.iter().flat_map(|die| die.rolls(db)).enumerate() {
+            if i > 0 {
+                push_text(parts, ", ");
+            }
+
+            parts.push(Part::Roll(roll));
+        }
+
+        push_text(parts, ")");
+    }
+}
+
+impl Die {
+    /// Every value the die rolled, ending with the one it settled on.
+    fn rolls(&self, db: &Database) -> Vec<DieRoll> {
+        let last = self.values.len().saturating_sub(1);
+
+        self.values
+            .iter()
+            .enumerate()
+            .map(|(i, idx)| {
+                let (value, exploded) = match &db.get(*idx).expr {
+                    Expr::Literal(literal) => (literal.value(), literal.exploded),
+                    _ => unreachable!(),
+                };
+                let rerolled = i < last;
+
+                DieRoll {
+                    value,
+                    dropped: !self.kept,
+                    rerolled,
+                    exploded,
+                    crit: self.crit && !rerolled,
+                    fumble: self.fumble && !rerolled,
+                }
+            })
+            .collect()
+    }
+}
+
+/// Adds text to the breakdown, joining it onto the text before it.
+fn push_text(parts: &mut Vec<Part>, text: &str) {
+    if let Some(Part::Text(last)) = parts.last_mut() {
+        last.push_str(text);
+    } else {
+        parts.push(Part::Text(text.to_string()));
+    }
+}
+
+/// Writes operations the way they are written in an expression, like `kh3`.
+pub(super) fn ops_text(ops: &[SetOperation]) -> String {
+    let mut out = String::new();
+
+    for op in ops {
+        let name = match op.op {
+            SetOp::Keep => "k",
+            SetOp::Drop => "p",
+            SetOp::Reroll => "rr",
+            SetOp::RerollOnce => "ro",
+            SetOp::RerollAdd => "ra",
+            SetOp::Explode => "e",
+            SetOp::Min => "mi",
+            SetOp::Max => "ma",
+            SetOp::CritSuccess => "cs",
+            SetOp::CritFail => "cf",
+        };
+        let sel = match op.sel {
+            SetSel::Number => "",
+            SetSel::Highest => "h",
+            SetSel::Lowest => "l",
+            SetSel::Greater => ">",
+            SetSel::Less => "<",
+        };
+
+        write!(out, "{}{}", name, sel).unwrap();
+
+        if let Some(num) = op.num {
+            write!(out, "{}", num).unwrap();
+        }
+    }
+
+    out
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::{roll_with, FixedRolls, RollContext};
+
+    fn breakdown(input: &str, rolls: &[u64]) -> String {
+        let root = ast::Root::cast(parser::parse(input).syntax()).unwrap();
+
+        let result = roll_with(root, RollContext::new(FixedRolls::new(rolls.iter().copied())));
+
+        result.breakdown().to_string()
+    }
+
+    #[test]
+    fn keep_highest() {
+        assert_eq!(breakdown("4d6kh3 + 2", &[6, 5, 2, 4]), "4d6kh3 (6, 5, ~~2~~, 4) + 2 = 17");
+    }
+
+    #[test]
+    fn rerolls_are_struck_through() {
+        assert_eq!(breakdown("2d6rr1", &[1, 1, 4, 3]), "2d6rr1 (~~1~~, 4, ~~1~~, 3) = 7");
+    }
+
+    #[test]
+    fn explosions_are_marked() {
+        assert_eq!(breakdown("2d6e6", &[6, 2, 6, 3]), "2d6e6 (6!, 2, 6!, 3) = 17");
+    }
+
+    #[test]
+    fn clamped_dice_show_their_final_value() {
+        assert_eq!(breakdown("2d6mi3", &[1, 5]), "2d6mi3 (3, 5) = 8");
+    }
+
+    #[test]
+    fn sets() {
+        assert_eq!(breakdown("(1d20, 5)kh1", &[13]), "(1d20 (13), ~~5~~)kh1 = 13");
+        assert_eq!(breakdown("(1d4,)", &[2]), "(1d4 (2),) = 2");
+    }
+
+    #[test]
+    fn parentheses_are_only_added_where_needed() {
+        assert_eq!(breakdown("((2 * 3)) + 1", &[]), "2 * 3 + 1 = 7");
+        assert_eq!(breakdown("2 * (3 + 1)", &[]), "2 * (3 + 1) = 8");
+        assert_eq!(breakdown("10 - (3 - 1)", &[]), "10 - (3 - 1) = 8");
+        assert_eq!(breakdown("-(1d4 + 1)", &[3]), "-(1d4 (3) + 1) = -4");
+    }
+
+    #[test]
+    fn tags_bands_and_versus() {
+        assert_eq!(breakdown("(1d6 + 1)[fire]", &[4]), "(1d6 (4) + 1)[fire] = 5");
+        assert_eq!(breakdown("1d20 -> { <10: miss, >=10: hit }", &[12]), "1d20 (12) = 12");
+        assert_eq!(breakdown("1d20 + 2 vs 15", &[12]), "1d20 (12) + 2 vs 15 = -1");
+    }
+
+    #[test]
+    fn missing_expressions() {
+        assert_eq!(breakdown("1 +", &[]), "1 + ? = 1");
+    }
+}
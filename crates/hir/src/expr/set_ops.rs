@@ -1,19 +1,29 @@
 use super::*;
+use std::cmp::Reverse;
 use std::collections::HashSet;
 use std::iter::FromIterator;
 
 
+/// The most targets a selector can pick: `h` and `l` pick `num` of them, the comparison
+/// selectors pick every match.
+fn default_max_targets(sel: SetSel, num: u64) -> usize {
+    match sel {
+        SetSel::Highest | SetSel::Lowest => num as usize,
+        SetSel::Number | SetSel::Greater | SetSel::Less => usize::MAX,
+    }
+}
+
 fn select_dice(op: &SetOperation, target: &mut Dice, db: &mut Database, max_targets: Option<usize>) -> HashSet<usize> {
     let SetOperation { op: _, sel, num } = op;
     let num = num.unwrap();
     let inum = num as i64;
 
-    let max_targets = max_targets.unwrap_or(num as usize);
+    let max_targets = max_targets.unwrap_or_else(|| default_max_targets(*sel, num));
 
     let res =
         target.values
             .iter()
-            .map(|d| d.total(db))
+            .map(|d| d.value(db))
             .enumerate();
 
     let res: Vec<usize> = match sel {
@@ -27,7 +37,7 @@ fn select_dice(op: &SetOperation, target: &mut Dice, db: &mut Database, max_targ
         }
         SetSel::Lowest => {
             let mut res: Vec<_> = res.collect();
-            res.sort_by(|(_, d1), (_, d2)| d1.cmp(d2));
+            res.sort_by_key(|(_, d)| *d);
             res.iter()
                 .take(max_targets)
                 .map(|(i, _)| *i)
@@ -57,21 +67,25 @@ fn select_dice(op: &SetOperation, target: &mut Dice, db: &mut Database, max_targ
 }
 
 
+/// Items whose totals fail, like by dividing by zero, rank below every other item and match no
+/// number.
 fn select_set(op: &SetOperation, target: &mut Set, db: &mut Database) -> HashSet<usize> {
     let SetOperation { op: _, sel, num } = op;
-    let max_targets = num.unwrap() as usize;
-    let inum = max_targets as i64;
+    let num = num.unwrap();
+    let inum = num as i64;
+
+    let max_targets = default_max_targets(*sel, num);
 
     let res =
         target.items
             .iter()
-            .map(|d| db.get(*d))
+            .map(|d| db.get(*d).total(db).ok())
             .enumerate();
 
     let res: Vec<usize> = match sel {
         SetSel::Highest => {
             let mut res: Vec<_> = res.collect();
-            res.sort_by(|(_, d1), (_, d2)| d2.total(db).cmp(&d1.total(db)));
+            res.sort_by_key(|(_, d)| Reverse(*d));
             res.iter()
                 .take(max_targets)
                 .map(|(i, _)| *i)
@@ -79,26 +93,26 @@ fn select_set(op: &SetOperation, target: &mut Set, db: &mut Database) -> HashSet
         }
         SetSel::Lowest => {
             let mut res: Vec<_> = res.collect();
-            res.sort_by(|(_, d1), (_, d2)| d1.total(db).cmp(&d2.total(db)));
+            res.sort_by_key(|(_, d)| *d);
             res.iter()
                 .take(max_targets)
                 .map(|(i, _)| *i)
                 .collect()
         }
         SetSel::Number => {
-            res.filter(|(_, d)| d.total(db) == inum)
+            res.filter(|(_, d)| *d == Some(inum))
                 .take(max_targets)
                 .map(|(i, _)| i)
                 .collect()
         }
         SetSel::Greater => {
-            res.filter(|(_, d)| d.total(db) > inum)
+            res.filter(|(_, d)| d.is_some_and(|d| d > inum))
                 .take(max_targets)
                 .map(|(i, _)| i)
                 .collect()
         }
         SetSel::Less => {
-            res.filter(|(_, d)| d.total(db) < inum)
+            res.filter(|(_, d)| d.is_some_and(|d| d < inum))
                 .take(max_targets)
                 .map(|(i, _)| i)
                 .collect()
@@ -118,13 +132,15 @@ pub(super) fn operate_on_dice(op: &SetOperation, target: &mut Dice, db: &mut Dat
         SetOp::RerollAdd => explode_dice_once(op, target, db),
         SetOp::Min => min_dice(op, target, db),
         SetOp::Max => max_dice(op, target, db),
+        // Crit ranges only flag dice once every other operation has been applied.
+        SetOp::CritSuccess | SetOp::CritFail => {},
     }
 }
 
 pub(super) fn operate_on_set(op: &SetOperation, target: &mut Set, db: &mut Database) {
-    match op.op {
-        SetOp::Keep | SetOp::Drop => {},
-        _ => panic!("Keep and Drop are the only operations valid on sets."),
+    // Keep and Drop are the only operations that apply to sets; any others are ignored.
+    if !matches!(op.op, SetOp::Keep | SetOp::Drop) {
+        return;
     }
 
     let selection: HashSet<usize> = select_set(op, target, db);
@@ -152,7 +168,11 @@ fn keep_or_drop_dice(op: &SetOperation, target: &mut Dice, db: &mut Database) {
 fn reroll_dice(op: &SetOperation, target: &mut Dice, db: &mut Database) {
     let mut to_reroll: HashSet<usize> = select_dice(op, target, db, None);
 
-    while !to_reroll.is_empty() {
+    for _ in 0..MAX_REPEATS {
+        if to_reroll.is_empty() {
+            break;
+        }
+
         for (i, d) in target.values.iter_mut().enumerate() {
             if to_reroll.contains(&i) {
                 d.reroll(db);
@@ -177,27 +197,34 @@ fn explode_dice(op: &SetOperation, target: &mut Dice, db: &mut Database) {
     let mut already_exploded: HashSet<usize> = HashSet::new();
     let mut to_explode: HashSet<usize> = select_dice(op, target, db, None);
 
-    while !to_explode.is_empty() {
-        for (i, d) in target.values.iter_mut().enumerate() {
-            if to_explode.contains(&i) {
-                d.explode(db);
-            }
+    for _ in 0..MAX_REPEATS {
+        if to_explode.is_empty() {
+            break;
         }
 
-        already_exploded = already_exploded.union(&to_explode).map(|x| *x).collect();
+        explode_selected(&to_explode, target, db);
+
+        already_exploded = already_exploded.union(&to_explode).copied().collect();
         to_explode = select_dice(op, target, db, None)
             .difference(&already_exploded)
-            .map(|x| *x).
-            collect();
+            .copied()
+            .collect();
     }
 }
 
 fn explode_dice_once(op: &SetOperation, target: &mut Dice, db: &mut Database) {
     let to_explode: HashSet<usize> = select_dice(op, target, db, None);
 
-    for (i, d) in target.values.iter_mut().enumerate() {
-        if to_explode.contains(&i) {
-            d.explode(db);
+    explode_selected(&to_explode, target, db);
+}
+
+/// Marks each selected die as exploded and rolls one extra die for it, in index order so that
+/// the extra dice always come out in the same order.
+fn explode_selected(selection: &HashSet<usize>, target: &mut Dice, db: &mut Database) {
+    for i in 0..target.values.len() {
+        if selection.contains(&i) {
+            target.values[i].explode(db);
+            target.roll_another(db);
         }
     }
 }
@@ -207,7 +234,7 @@ fn min_dice(op: &SetOperation, target: &mut Dice, db: &mut Database) {
     let imin = min as i64;
 
     for d in target.values.iter_mut() {
-        if d.total(db) < imin {
+        if d.value(db) < imin {
             d.force_value(min, db);
         }
     }
@@ -218,7 +245,7 @@ fn max_dice(op: &SetOperation, target: &mut Dice, db: &mut Database) {
     let imax = max as i64;
 
     for d in target.values.iter_mut() {
-        if d.total(db) > imax {
+        if d.value(db) > imax {
             d.force_value(max, db);
         }
     }
@@ -228,41 +255,38 @@ fn max_dice(op: &SetOperation, target: &mut Dice, db: &mut Database) {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::{SEED, RollContext};
+    use crate::{Draw, SEED, RollContext};
     use rand::prelude::*;
+    use text_size::TextRange;
 
-    fn parse(input: &str) -> ast::Root {
-        ast::Root::cast(parser::parse(input).syntax()).unwrap()
-    }
-
-    fn roll(count: usize, sides: u64, db: &mut Database) -> (Vec<u64>, Vec<ExprIdx>) {
+    /// Draws a value for each of `dice`, in order, as the die with that id, logging each draw.
+    fn roll(dice: &[usize], sides: u64, db: &mut Database) -> (Vec<u64>, Vec<ExprIdx>) {
         let distr = rand::distributions::Uniform::new_inclusive(1, sides);
 
-        StdRng::seed_from_u64(SEED).sample_iter(distr).take(count)
-            .map(|x| (x, db.alloc(Expression::new(Expr::literal(Some(x))))))
+        StdRng::seed_from_u64(SEED).sample_iter(distr).zip(dice)
+            .map(|(x, &die)| {
+                db.log.push(Draw { die, sides, value: x, range: TextRange::default() });
+
+                (x, db.alloc(Expression::new(Expr::literal(Some(x)))))
+            })
             .unzip()
     }
 
-    fn check_dice(input_dice: Expression, db: &mut Database, ops: Vec<SetOperation>, expected_output: Dice, expected_db: &mut Database) {
-        let mut input_dice = input_dice;
-
-        for op in ops.iter() {
-            op.operate(&mut input_dice, db);
+    fn explode(idxs: &[ExprIdx], db: &mut Database) {
+        for idx in idxs {
+            if let Expression { expr: Expr::Literal(literal), .. } = db.get_mut(*idx) {
+                literal.explode();
+            }
         }
+    }
 
-        if let Expression { expr: Expr::Dice(dice), .. } = input_dice {
-            assert_eq!(dice, expected_output);
-            assert_eq!(db, expected_db);
-        } else {
-            panic!()
-        }
+    fn check_dice(input_dice: Dice, db: &Database, expected_output: Dice, expected_db: &Database) {
+        assert_eq!(input_dice, expected_output);
+        assert_eq!(db, expected_db);
     }
 
     fn default_db() -> Database {
-        Database {
-            exprs: la_arena::Arena::new(),
-            ctx: RollContext::new(StdRng::seed_from_u64(SEED)),
-        }
+        Database::new(RollContext::new(StdRng::seed_from_u64(SEED)))
     }
 
     #[test]
@@ -270,31 +294,42 @@ mod tests {
         let mut db = default_db();
         let input_dice = Dice::new(Some(2), Some(20), vec![
             SetOperation::new(SetOp::Keep, SetSel::Highest, Some(1)),
-        ], &mut db);
+        ], TextRange::default(), &mut db);
 
         let mut output_db = default_db();
 
         // [5, 15]
-        let (rolls, idxs) = roll(2, 20, &mut output_db);
-        let mut output_dice = Dice {
+        let (_, idxs) = roll(&[0, 1], 20, &mut output_db);
+        let output_dice = Dice {
             count: Some(2),
             sides: Some(20),
+            range: TextRange::default(),
             ops: vec![
                 SetOperation::new(SetOp::Keep, SetSel::Highest, Some(1)),
             ],
             values: vec![
                 Die {
+                    id: 0,
                     sides: 20,
+                    range: TextRange::default(),
                     values: vec![idxs[0]],
                     kept: false,
+                    crit: false,
+                    fumble: false,
                 },
                 Die {
+                    id: 1,
                     sides: 20,
+                    range: TextRange::default(),
                     values: vec![idxs[1]],
                     kept: true,
+                    crit: false,
+                    fumble: false,
                 },
             ],
         };
+
+        check_dice(input_dice, &db, output_dice, &output_db);
     }
 
     #[test]
@@ -302,32 +337,43 @@ mod tests {
         let mut db = default_db();
         let input_dice = Dice::new(Some(2), Some(20), vec![
             SetOperation::new(SetOp::Drop, SetSel::Highest, Some(1)),
-        ], &mut db);
+        ], TextRange::default(), &mut db);
 
         let mut output_db = default_db();
 
         // [5, 15]
-        let (rolls, idxs) = roll(2, 20, &mut output_db);
+        let (_, idxs) = roll(&[0, 1], 20, &mut output_db);
 
-        let mut output_dice = Dice {
+        let output_dice = Dice {
             count: Some(2),
             sides: Some(20),
+            range: TextRange::default(),
             ops: vec![
                 SetOperation::new(SetOp::Drop, SetSel::Highest, Some(1)),
             ],
             values: vec![
                 Die {
+                    id: 0,
                     sides: 20,
+                    range: TextRange::default(),
                     values: vec![idxs[0]],
                     kept: true,
+                    crit: false,
+                    fumble: false,
                 },
                 Die {
+                    id: 1,
                     sides: 20,
+                    range: TextRange::default(),
                     values: vec![idxs[1]],
                     kept: false,
+                    crit: false,
+                    fumble: false,
                 },
             ],
         };
+
+        check_dice(input_dice, &db, output_dice, &output_db);
     }
 
     #[test]
@@ -335,31 +381,85 @@ mod tests {
         let mut db = default_db();
         let input_dice = Dice::new(Some(2), Some(20), vec![
             SetOperation::new(SetOp::RerollOnce, SetSel::Highest, Some(1)),
-        ], &mut db);
+        ], TextRange::default(), &mut db);
 
         let mut output_db = default_db();
 
         // [5, 15, ..]
-        let (rolls, idxs) = roll(3, 20, &mut output_db);
-        let mut output_dice = Dice {
+        let (_, idxs) = roll(&[0, 1, 1], 20, &mut output_db);
+        let output_dice = Dice {
             count: Some(2),
             sides: Some(20),
+            range: TextRange::default(),
             ops: vec![
                 SetOperation::new(SetOp::RerollOnce, SetSel::Highest, Some(1)),
             ],
             values: vec![
                 Die {
+                    id: 0,
                     sides: 20,
+                    range: TextRange::default(),
                     values: vec![idxs[0]],
                     kept: true,
+                    crit: false,
+                    fumble: false,
                 },
                 Die {
+                    id: 1,
                     sides: 20,
+                    range: TextRange::default(),
                     values: vec![idxs[1], idxs[2]],
                     kept: true,
+                    crit: false,
+                    fumble: false,
                 },
             ],
         };
+
+        check_dice(input_dice, &db, output_dice, &output_db);
+    }
+
+    #[test]
+    fn rerolled_dice_keep_their_earlier_values() {
+        let mut db = default_db();
+        let input_dice = Dice::new(Some(2), Some(20), vec![
+            SetOperation::new(SetOp::RerollOnce, SetSel::Greater, Some(10)),
+        ], TextRange::default(), &mut db);
+
+        let mut output_db = default_db();
+
+        // [5, 15, 16]
+        let (_, idxs) = roll(&[0, 1, 1], 20, &mut output_db);
+        let output_dice = Dice {
+            count: Some(2),
+            sides: Some(20),
+            range: TextRange::default(),
+            ops: vec![
+                SetOperation::new(SetOp::RerollOnce, SetSel::Greater, Some(10)),
+            ],
+            values: vec![
+                Die {
+                    id: 0,
+                    sides: 20,
+                    range: TextRange::default(),
+                    values: vec![idxs[0]],
+                    kept: true,
+                    crit: false,
+                    fumble: false,
+                },
+                Die {
+                    id: 1,
+                    sides: 20,
+                    range: TextRange::default(),
+                    values: vec![idxs[1], idxs[2]],
+                    kept: true,
+                    crit: false,
+                    fumble: false,
+                },
+            ],
+        };
+
+        check_dice(input_dice, &db, output_dice, &output_db);
     }
 
     #[test]
@@ -368,33 +468,43 @@ mod tests {
         let input_dice = Dice::new(Some(2), Some(20), vec![
             SetOperation::new(SetOp::RerollOnce, SetSel::Lowest, Some(1)),
             SetOperation::new(SetOp::Keep, SetSel::Highest, Some(1)),
-        ], &mut db);
+        ], TextRange::default(), &mut db);
 
         let mut output_db = default_db();
 
         // [5, 15, 16]
-        let (rolls, idxs) = roll(3, 20, &mut output_db);
-        // dbg!(&rolls, &idxs);
-        let mut output_dice = Dice {
+        let (_, idxs) = roll(&[0, 1, 0], 20, &mut output_db);
+        let output_dice = Dice {
             count: Some(2),
             sides: Some(20),
+            range: TextRange::default(),
             ops: vec![
                 SetOperation::new(SetOp::RerollOnce, SetSel::Lowest, Some(1)),
                 SetOperation::new(SetOp::Keep, SetSel::Highest, Some(1)),
             ],
             values: vec![
                 Die {
+                    id: 0,
                     sides: 20,
+                    range: TextRange::default(),
                     values: vec![idxs[0], idxs[2]],
                     kept: true,
+                    crit: false,
+                    fumble: false,
                 },
                 Die {
+                    id: 1,
                     sides: 20,
+                    range: TextRange::default(),
                     values: vec![idxs[1]],
                     kept: false,
+                    crit: false,
+                    fumble: false,
                 },
             ],
         };
+
+        check_dice(input_dice, &db, output_dice, &output_db);
     }
 
     #[test]
@@ -402,23 +512,29 @@ mod tests {
         let mut db = default_db();
         let input_dice = Dice::new(Some(2), Some(20), vec![
             SetOperation::new(SetOp::Explode, SetSel::Greater, Some(10)),
-        ], &mut db);
+        ], TextRange::default(), &mut db);
 
         let mut output_db = default_db();
 
         // [5, 15, 16, 17, 5]
-        let (rolls, idxs) = roll(5, 20, &mut output_db);
-        // dbg!(&rolls, &idxs);
-        let mut output_dice = Dice {
+        let (_, idxs) = roll(&[0, 1, 2, 3, 4], 20, &mut output_db);
+        explode(&idxs[1..4], &mut output_db);
+        let output_dice = Dice {
             count: Some(2),
             sides: Some(20),
+            range: TextRange::default(),
             ops: vec![
                 SetOperation::new(SetOp::Explode, SetSel::Greater, Some(10)),
             ],
-            values: idxs.into_iter()
-                .map(|idx| Die { sides: 20, values: vec![idx], kept: true })
+            values: idxs.into_iter().enumerate()
+                .map(|(id, idx)| Die {
+                    id, sides: 20, range: TextRange::default(),
+                    values: vec![idx], kept: true, crit: false, fumble: false,
+                })
                 .collect(),
         };
+
+        check_dice(input_dice, &db, output_dice, &output_db);
     }
 
     #[test]
@@ -426,67 +542,127 @@ mod tests {
         let mut db = default_db();
         let input_dice = Dice::new(Some(2), Some(20), vec![
             SetOperation::new(SetOp::RerollAdd, SetSel::Number, Some(5)),
-        ], &mut db);
+        ], TextRange::default(), &mut db);
 
         let mut output_db = default_db();
 
         // [5, 15, 16]
-        let (rolls, idxs) = roll(3, 20, &mut output_db);
-        // dbg!(&rolls, &idxs);
-        let mut output_dice = Dice {
+        let (_, idxs) = roll(&[0, 1, 2], 20, &mut output_db);
+        explode(&idxs[..1], &mut output_db);
+        let output_dice = Dice {
             count: Some(2),
             sides: Some(20),
+            range: TextRange::default(),
             ops: vec![
                 SetOperation::new(SetOp::RerollAdd, SetSel::Number, Some(5)),
             ],
-            values: idxs.into_iter()
-                .map(|idx| Die { sides: 20, values: vec![idx], kept: true })
+            values: idxs.into_iter().enumerate()
+                .map(|(id, idx)| Die {
+                    id, sides: 20, range: TextRange::default(),
+                    values: vec![idx], kept: true, crit: false, fumble: false,
+                })
                 .collect(),
         };
+
+        check_dice(input_dice, &db, output_dice, &output_db);
     }
 
     #[test]
     fn keep_highest_set() {
         let mut db = default_db();
-        let tmp = Expr::dice(Some(2), Some(20), vec![], &mut db);
-        let input_set = Set {
-            items: vec![
-                db.alloc(Expression::new(tmp)),
-                db.alloc(Expression::new(Expr::literal(Some(7)))),
-                db.alloc(Expression::new(Expr::literal(Some(25)))),
-            ],
-            ops: vec![
-                SetOperation::new(SetOp::Keep, SetSel::Highest, Some(1)),
-            ],
-        };
+        let dice = Expr::dice(Some(2), Some(20), vec![], TextRange::default(), &mut db);
+        let items = vec![
+            db.alloc(Expression::new(dice)),
+            db.alloc(Expression::new(Expr::literal(Some(7)))),
+            db.alloc(Expression::new(Expr::literal(Some(25)))),
+        ];
+        let input_set = Set::new(items, vec![
+            SetOperation::new(SetOp::Keep, SetSel::Highest, Some(1)),
+        ], &mut db);
 
-        let (rolls, idxs) = roll(2, 20, &mut db);
+        let mut output_db = default_db();
 
-        let mut output_set = Set {
+        // [5, 15]
+        let (_, idxs) = roll(&[0, 1], 20, &mut output_db);
+        let output_set = Set {
             items: vec![
-                db.alloc(Expression {
+                output_db.alloc(Expression {
                     expr: Expr::Dice(Dice {
                         count: Some(2),
                         sides: Some(20),
                         ops: Vec::new(),
-                        values: idxs.into_iter()
-                            .map(|idx| Die { sides: 20, values: vec![idx], kept: true })
-                            .collect()
+                        values: idxs.into_iter().enumerate()
+                            .map(|(id, idx)| Die {
+                                id, sides: 20, range: TextRange::default(),
+                                values: vec![idx], kept: true, crit: false, fumble: false,
+                            })
+                            .collect(),
+                        range: TextRange::default(),
                     }),
                     kept: false,
+                    range: None,
                 }),
-                db.alloc(Expression {
+                output_db.alloc(Expression {
                     expr: Expr::literal(Some(7)),
                     kept: false,
+                    range: None,
                 }),
-                db.alloc(Expression {
+                output_db.alloc(Expression {
                     expr: Expr::literal(Some(25)),
                     kept: true,
+                    range: None,
                 }),
             ],
             ops: vec![
                 SetOperation::new(SetOp::Keep, SetSel::Highest, Some(1)),
             ],
         };
+
+        assert_eq!(input_set, output_set);
+        assert_eq!(db, output_db);
+    }
+
+    /// Rolls `input` on `rolls`, returning its total and the values each of its dice rolled.
+    fn roll_fixed(input: &str, rolls: &[u64]) -> (i64, Vec<Vec<i64>>) {
+        let root = ast::Root::cast(parser::parse(input).syntax()).unwrap();
+        let result = crate::roll_with(root, RollContext::new(crate::FixedRolls::new(rolls.to_vec())));
+        let root = result.root();
+
+        (root.total().unwrap(), root.dice().map(|die| die.values()).collect())
+    }
+
+    #[test]
+    fn dropped_dice_do_not_count_towards_the_total() {
+        assert_eq!(roll_fixed("4d6kh3", &[6, 5, 2, 4]).0, 15);
+        assert_eq!(roll_fixed("4d6pl1", &[6, 5, 2, 4]).0, 15);
+        assert_eq!(roll_fixed("4d6p<5", &[6, 5, 2, 4]).0, 11);
+    }
+
+    #[test]
+    fn rerolls_keep_every_value_they_rolled() {
+        let (total, values) = roll_fixed("2d6rr1", &[1, 3, 1, 4]);
+
+        assert_eq!(total, 7);
+        assert_eq!(values, vec![vec![1, 1, 4], vec![3]]);
+    }
+
+    #[test]
+    fn comparison_selectors_match_every_die() {
+        // `k>2` keeps all four dice over 2, not just the first two of them.
+        assert_eq!(roll_fixed("5d6k>2", &[3, 4, 5, 6, 1]).0, 18);
+        assert_eq!(
+            roll_fixed("3d6ro1", &[1, 1, 1, 2, 3, 4]).1,
+            vec![vec![1, 2], vec![1, 3], vec![1, 4]],
+        );
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn repeating_operations_stop_after_max_repeats() {
+        let (_, values) = roll_fixed("1d6rr<7", &[3; MAX_REPEATS + 1]);
+        assert_eq!(values, vec![vec![3; MAX_REPEATS + 1]]);
+
+        let (total, values) = roll_fixed("1d6e>0", &[6; MAX_REPEATS + 1]);
+        assert_eq!(values.len(), MAX_REPEATS + 1);
+        assert_eq!(total, 6 * (MAX_REPEATS as i64 + 1));
+    }
+}
@@ -0,0 +1,311 @@
+use super::breakdown::ops_text;
+use super::*;
+use std::fmt;
+
+
+/// A read-only view of one part of a rolled expression. See `RollResult::root`.
+#[derive(Clone, Copy)]
+pub struct NodeView<'a> {
+    expr: &'a Expression,
+    db: &'a Database,
+}
+
+impl<'a> NodeView<'a> {
+    pub(in crate) fn new(expr: &'a Expression, db: &'a Database) -> Self {
+        Self { expr, db }
+    }
+
+    pub fn kind(&self) -> NodeViewKind<'a> {
+        match &self.expr.expr {
+            Expr::Missing => NodeViewKind::Missing,
+            Expr::Band(band) => NodeViewKind::Band { label: band.label(self.db).ok().flatten() },
+            Expr::Binary(binary) => NodeViewKind::Binary { op: binary.op.symbol() },
+            Expr::Dice(dice) => NodeViewKind::Dice { count: dice.count, sides: dice.sides },
+            Expr::Literal(literal) => NodeViewKind::Literal { value: literal.values.last().copied() },
+            Expr::Set(_) => NodeViewKind::Set,
+            Expr::Tagged(tagged) => NodeViewKind::Tagged { tag: &tagged.tag },
+            Expr::Unary(unary) => NodeViewKind::Unary {
+                op: match unary.op {
+                    UnaryOp::Neg => "-",
+                },
+            },
+            Expr::Versus(_) => NodeViewKind::Versus,
+        }
+    }
+
+    /// The total of the node, whether or not it counts towards the total of its parent, or why
+    /// it has none.
+    pub fn total(&self) -> Result<i64, RollError> {
+        self.expr.expr.total(self.db)
+    }
+
+    /// Where the node was written in the source text, or `None` if it is missing from it.
+    pub fn range(&self) -> Option<TextRange> {
+        self.expr.range
+    }
+
+    /// Whether the node counts towards the total of its parent, which it doesn't if it was
+    /// dropped from a set.
+    pub fn is_kept(&self) -> bool {
+        self.expr.kept
+    }
+
+    /// The operations applied to dice or a set, as they are written, like `kh3`. Other nodes
+    /// have none.
+    pub fn ops(&self) -> String {
+        match &self.expr.expr {
+            Expr::Dice(dice) => ops_text(&dice.ops),
+            Expr::Set(set) => ops_text(&set.ops),
+            _ => String::new(),
+        }
+    }
+
+    /// The operands of the node, in the order they were written.
+    pub fn children(&self) -> impl Iterator<Item=NodeView<'a>> + 'a {
+        let children = match &self.expr.expr {
+            Expr::Missing | Expr::Dice(_) | Expr::Literal(_) => Vec::new(),
+            Expr::Band(band) => vec![band.expr],
+            Expr::Binary(binary) => vec![binary.lhs, binary.rhs],
+            Expr::Set(set) => set.items.clone(),
+            Expr::Tagged(tagged) => vec![tagged.expr],
+            Expr::Unary(unary) => vec![unary.expr],
+            Expr::Versus(versus) => vec![versus.lhs, versus.rhs],
+        };
+        let db = self.db;
+
+        children.into_iter().map(move |idx| NodeView::new(db.get(idx), db))
+    }
+
+    /// The node followed by every node below it, depth first in the order they were written.
+    pub fn descendants(&self) -> impl Iterator<Item=NodeView<'a>> + 'a {
+        let mut stack = vec![*self];
+
+        std::iter::from_fn(move || {
+            let node = stack.pop()?;
+
+            let children: Vec<_> = node.children().collect();
+            stack.extend(children.into_iter().rev());
+
+            Some(node)
+        })
+    }
+
+    /// The dice rolled by a dice node, including any added by explosions. Other nodes have none.
+    pub fn dice(&self) -> impl Iterator<Item=DieView<'a>> + 'a {
+        let dice: &'a [Die] = match &self.expr.expr {
+            Expr::Dice(dice) => &dice.values,
+            _ => &[],
+        };
+        let db = self.db;
+
+        dice.iter().map(move |die| DieView { die, db })
+    }
+}
+
+impl fmt::Debug for NodeView<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("NodeView")
+            .field("kind", &self.kind())
+            .field("total", &self.total())
+            .field("range", &self.range())
+            .field("kept", &self.is_kept())
+            .finish()
+    }
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum NodeViewKind<'a> {
+    Missing,
+    Band {
+        /// The label of the band the total fell into, if any.
+        label: Option<&'a str>,
+    },
+    Binary {
+        /// `+`, `-`, `*` or `/`.
+        op: &'static str,
+    },
+    Dice {
+        count: Option<u64>,
+        sides: Option<u64>,
+    },
+    Literal {
+        value: Option<u64>,
+    },
+    Set,
+    Tagged {
+        tag: &'a str,
+    },
+    Unary {
+        /// `-`.
+        op: &'static str,
+    },
+    Versus,
+}
+
+
+/// A read-only view of one die of a dice node.
+#[derive(Clone, Copy)]
+pub struct DieView<'a> {
+    die: &'a Die,
+    db: &'a Database,
+}
+
+impl<'a> DieView<'a> {
+    pub fn sides(&self) -> u64 {
+        self.die.sides
+    }
+
+    /// The range in the source text of the dice the die was rolled for, like `2d6e6`.
+    pub fn range(&self) -> TextRange {
+        self.die.range
+    }
+
+    /// The value the die settled on.
+    pub fn value(&self) -> i64 {
+        self.die.value(self.db)
+    }
+
+    /// Every value the die rolled, in order. All but the last were rerolled.
+    pub fn values(&self) -> Vec<i64> {
+        self.literals().map(|literal| literal.value()).collect()
+    }
+
+    pub fn is_kept(&self) -> bool {
+        self.die.kept
+    }
+
+    /// Whether the die exploded, adding another die to its pool.
+    pub fn is_exploded(&self) -> bool {
+        self.literals().any(|literal| literal.exploded)
+    }
+
+    pub fn is_rerolled(&self) -> bool {
+        self.die.values.len() > 1
+    }
+
+    pub fn is_crit(&self) -> bool {
+        self.die.crit
+    }
+
+    pub fn is_fumble(&self) -> bool {
+        self.die.fumble
+    }
+
+    fn literals(&self) -> impl Iterator<Item=&'a Literal> + 'a {
+        let db = self.db;
+
+        self.die.values.iter().map(move |idx| match &db.get(*idx).expr {
+            Expr::Literal(literal) => literal,
+            _ => unreachable!(),
+        })
+    }
+}
+
+impl fmt::Debug for DieView<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DieView")
+            .field("sides", &self.sides())
+            .field("values", &self.values())
+            .field("kept", &self.is_kept())
+            .finish()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+    use text_size::TextRange;
+
+    fn roll(input: &str, rolls: &[u64]) -> RollResult {
+        let root = ast::Root::cast(parser::parse(input).syntax()).unwrap();
+
+        roll_with(root, RollContext::new(FixedRolls::new(rolls.iter().copied())))
+    }
+
+    #[test]
+    fn walks_the_tree() {
+        let result = roll("(1d6 + 2)[fire] vs 10", &[4]);
+
+        let kinds: Vec<_> = result.root().descendants().map(|node| node.kind()).collect();
+
+        assert_eq!(kinds, vec![
+            NodeViewKind::Versus,
+            NodeViewKind::Tagged { tag: "fire" },
+            NodeViewKind::Binary { op: "+" },
+            NodeViewKind::Dice { count: Some(1), sides: Some(6) },
+            NodeViewKind::Literal { value: Some(2) },
+            NodeViewKind::Literal { value: Some(10) },
+        ]);
+    }
+
+    #[test]
+    fn nodes_have_totals_and_ranges() {
+        let result = roll("2 * 1d4", &[3]);
+        let root = result.root();
+        let children: Vec<_> = root.children().collect();
+
+        assert_eq!(root.total(), Ok(6));
+        assert_eq!(root.range(), Some(TextRange::new(0.into(), 7.into())));
+        assert_eq!(children[1].total(), Ok(3));
+        assert_eq!(children[1].range(), Some(TextRange::new(4.into(), 7.into())));
+    }
+
+    #[test]
+    fn dropped_set_items_are_not_kept() {
+        let result = roll("(1d20, 5)kh1", &[2]);
+        let items: Vec<_> = result.root().children().map(|item| item.is_kept()).collect();
+
+        assert_eq!(result.root().ops(), "kh1");
+        assert_eq!(items, vec![false, true]);
+    }
+
+    #[test]
+    fn dice() {
+        let result = roll("3d6rr1e6cs6kh2", &[1, 6, 2, 4, 5]);
+        let dice: Vec<_> = result.root().dice().collect();
+
+        assert_eq!(result.root().ops(), "rr1e6cs6kh2");
+        assert_eq!(dice.len(), 4);
+        assert_eq!(dice[0].values(), vec![1, 4]);
+        assert_eq!(dice[0].value(), 4);
+        assert!(dice[0].is_rerolled() && !dice[0].is_kept());
+        assert!(dice[1].is_exploded() && dice[1].is_crit());
+        assert!(!dice[2].is_kept());
+        assert_eq!(dice[3].values(), vec![5]);
+    }
+
+    #[test]
+    fn dice_know_where_they_were_written() {
+        let result = roll("1 + 2d6e6", &[6, 3, 2]);
+        let ranges: Vec<_> = result.root()
+            .descendants()
+            .flat_map(|node| node.dice())
+            .map(|die| die.range())
+            .collect();
+
+        assert_eq!(ranges, vec![TextRange::new(4.into(), 9.into()); 3]);
+    }
+
+    #[test]
+    fn node_at_finds_the_innermost_node() {
+        let result = roll("1d20 + (2d6, 3)kh1", &[12, 4, 5]);
+        let node_at = |offset: u32| result.node_at(offset.into()).map(|node| node.kind());
+
+        assert_eq!(node_at(2), Some(NodeViewKind::Dice { count: Some(1), sides: Some(20) }));
+        assert_eq!(node_at(5), Some(NodeViewKind::Binary { op: "+" }));
+        assert_eq!(node_at(8), Some(NodeViewKind::Dice { count: Some(2), sides: Some(6) }));
+        assert_eq!(node_at(13), Some(NodeViewKind::Literal { value: Some(3) }));
+        assert_eq!(node_at(16), Some(NodeViewKind::Set));
+        assert_eq!(node_at(18), None);
+    }
+
+    #[test]
+    fn other_nodes_have_no_dice() {
+        let result = roll("1 + 2", &[]);
+
+        assert_eq!(result.root().dice().count(), 0);
+        assert_eq!(result.root().ops(), "");
+    }
+}
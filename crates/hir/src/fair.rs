@@ -0,0 +1,191 @@
+use crate::{roll_with, RollContext, RollLog, RollResult};
+use rand::SeedableRng;
+use rand_chacha::ChaCha12Rng;
+use sha2::{Digest, Sha256};
+use std::fmt;
+
+
+/// A server's secret seed for provably fair rolls. It must be kept secret until the roll has been
+/// made, and should never be reused.
+pub type ServerSeed = [u8; 32];
+
+
+/// A published promise to roll with a particular seed, without giving the seed away.
+///
+/// A commitment is the SHA-256 hash of the seed. The server publishes it before the roll and
+/// reveals the seed afterwards, so anyone can check that the seed wasn't changed once the
+/// player's nonce and expression were known.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct Commitment([u8; 32]);
+
+impl Commitment {
+    pub fn new(seed: &ServerSeed) -> Self {
+        Self(Sha256::digest(seed).into())
+    }
+
+    pub fn as_bytes(&self) -> &[u8; 32] {
+        &self.0
+    }
+}
+
+impl From<[u8; 32]> for Commitment {
+    fn from(bytes: [u8; 32]) -> Self {
+        Self(bytes)
+    }
+}
+
+/// Formats the commitment as lowercase hex, as it is usually published.
+impl fmt::Display for Commitment {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.iter().try_for_each(|byte| write!(f, "{:02x}", byte))
+    }
+}
+
+
+impl RollContext {
+    /// A context whose dice are determined by a server seed, a client nonce, and the text of the
+    /// expression being rolled.
+    ///
+    /// The three are hashed together with SHA-256, each prefixed by its length, and the hash
+    /// seeds a ChaCha12 generator. Neither side can pick the dice alone: the server committed to
+    /// its seed before seeing the nonce, and the player chose the nonce without knowing the seed.
+    pub fn fair(seed: &ServerSeed, nonce: &[u8], expr: &str) -> Self {
+        let mut hasher = Sha256::new();
+
+        for part in [&seed[..], nonce, expr.as_bytes()] {
+            hasher.update((part.len() as u64).to_le_bytes());
+            hasher.update(part);
+        }
+
+        Self::new(ChaCha12Rng::from_seed(hasher.finalize().into()))
+    }
+}
+
+
+/// Rolls `ast` fairly with `RollContext::fair`, using its full source text as the expression.
+pub fn roll_fair(ast: ast::Root, seed: &ServerSeed, nonce: &[u8]) -> RollResult {
+    let ctx = RollContext::fair(seed, nonce, &ast.text());
+
+    roll_with(ast, ctx)
+}
+
+/// Checks a fair roll once its seed has been revealed.
+///
+/// The roll is verified if `seed` matches `commitment`, and rolling `ast` fairly with `seed` and
+/// `nonce` draws exactly the dice in `log`. The re-rolled result is returned on success.
+pub fn verify_fair(ast: ast::Root, commitment: &Commitment, seed: &ServerSeed, nonce: &[u8],
+                   log: &RollLog) -> Result<RollResult, FairError> {
+    if Commitment::new(seed) != *commitment {
+        return Err(FairError::CommitmentMismatch);
+    }
+
+    let result = roll_fair(ast, seed, nonce);
+
+    if result.log() == log {
+        Ok(result)
+    } else {
+        Err(FairError::RollMismatch)
+    }
+}
+
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum FairError {
+    /// The revealed seed isn't the one that was committed to.
+    CommitmentMismatch,
+    /// The seed, nonce, and expression don't roll the dice in the log.
+    RollMismatch,
+}
+
+impl fmt::Display for FairError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::CommitmentMismatch => write!(f, "the seed does not match the commitment"),
+            Self::RollMismatch => write!(f, "the seed and nonce do not produce the logged roll"),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SEED: ServerSeed = [7; 32];
+    const NONCE: &[u8] = b"player-1:42";
+
+    fn parse(input: &str) -> ast::Root {
+        ast::Root::cast(parser::parse(input).syntax()).unwrap()
+    }
+
+    fn draws(result: &RollResult) -> Vec<u64> {
+        result.log().draws().iter().map(|draw| draw.value).collect()
+    }
+
+    #[test]
+    fn commitment_is_sha256_of_seed() {
+        assert_eq!(
+            Commitment::new(&[0; 32]).to_string(),
+            "66687aadf862bd776c8fc18b8e9f8e20089714856ee233b3902a591d0d5f2925",
+        );
+    }
+
+    #[test]
+    fn fair_rolls_are_deterministic() {
+        let first = roll_fair(parse("4d6 + 1d20"), &SEED, NONCE);
+        let second = roll_fair(parse("4d6 + 1d20"), &SEED, NONCE);
+
+        assert_eq!(first.log(), second.log());
+    }
+
+    #[test]
+    fn fair_rolls_are_pinned() {
+        let rolled = roll_fair(parse("6d20"), &SEED, NONCE);
+
+        assert_eq!(draws(&rolled), vec![6, 19, 19, 16, 11, 14]);
+    }
+
+    #[test]
+    fn fair_rolls_depend_on_every_input() {
+        let rolled = draws(&roll_fair(parse("10d20"), &SEED, NONCE));
+
+        assert_ne!(draws(&roll_fair(parse("10d20"), &[8; 32], NONCE)), rolled);
+        assert_ne!(draws(&roll_fair(parse("10d20"), &SEED, b"player-1:43")), rolled);
+        assert_ne!(draws(&roll_fair(parse("10d20 "), &SEED, NONCE)), rolled);
+    }
+
+    #[test]
+    fn verifies_honest_roll() {
+        let commitment = Commitment::new(&SEED);
+        let rolled = roll_fair(parse("2d20kh1 + 5"), &SEED, NONCE);
+
+        let verified =
+            verify_fair(parse("2d20kh1 + 5"), &commitment, &SEED, NONCE, rolled.log()).unwrap();
+
+        assert_eq!(verified.total(), rolled.total());
+    }
+
+    #[test]
+    fn rejects_swapped_seed() {
+        let commitment = Commitment::new(&SEED);
+        let rolled = roll_fair(parse("1d20"), &[8; 32], NONCE);
+
+        assert_eq!(
+            verify_fair(parse("1d20"), &commitment, &[8; 32], NONCE, rolled.log()).err(),
+            Some(FairError::CommitmentMismatch),
+        );
+    }
+
+    #[test]
+    fn rejects_tampered_roll() {
+        let commitment = Commitment::new(&SEED);
+        let rolled = roll_fair(parse("1d20"), &SEED, NONCE);
+        let other = roll_fair(parse("1d20"), &SEED, b"player-2:42");
+
+        assert_eq!(
+            verify_fair(parse("1d20"), &commitment, &SEED, NONCE, other.log()).err(),
+            Some(FairError::RollMismatch),
+        );
+        assert!(verify_fair(parse("1d20"), &commitment, &SEED, NONCE, rolled.log()).is_ok());
+    }
+}
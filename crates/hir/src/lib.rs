@@ -4,8 +4,49 @@ pub(crate) use database::Database;
 mod expr;
 pub(crate) use expr::Expression;
 pub(crate) use expr::*;
+pub use expr::{DieView, NodeView, NodeViewKind, ZeroDicePool};
+
+mod program;
+pub use program::Program;
+pub(crate) use program::{Node, NodeIdx};
+
+mod simulate;
+pub use simulate::{simulate, simulate_with_seed, Histogram, Simulation, SimulationError};
+
+mod bounds;
+pub use bounds::{Bound, Bounds};
+
+mod explain;
+pub use explain::explain;
+
+mod distribution;
+pub use distribution::{Comparison, Distribution};
+
+mod stats;
+pub use stats::{compare, Stats};
+
+mod fair;
+pub use fair::{roll_fair, verify_fair, Commitment, FairError, ServerSeed};
+
+mod log;
+pub use log::{replay, replay_with, Draw, ReplayError, RollLog};
+
+mod record;
+pub use record::{DieRecord, NodeKind, NodeRecord, RollRecord, SCHEMA_VERSION};
+
+mod render;
+pub use render::{Ansi, Breakdown, DieRoll, Html, Markdown, Part, Plain, Renderer};
+
+mod rng;
+pub use rng::{DiceRng, DieRequest, FixedRolls, ManualRolls};
 
 use rand::prelude::*;
+use rand_chacha::ChaCha12Rng;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::rc::Rc;
+use std::sync::Arc;
+use text_size::TextSize;
 
 
 pub(crate) type ExprIdx = la_arena::Idx<Expression>;
@@ -15,48 +56,276 @@ pub fn roll(ast: ast::Root) -> RollResult {
     RollResult::from(ast)
 }
 
+pub fn roll_with(ast: ast::Root, ctx: RollContext) -> RollResult {
+    RollResult::new(ast, ctx)
+}
+
+/// Rolls `ast` with dice that are determined entirely by `seed`.
+///
+/// Rolling the same expression with the same seed always rolls the same dice, on every run and
+/// every platform, so a roll can be regenerated later from just its expression and seed. See
+/// `RollContext::seeded` for how the dice are drawn.
+pub fn roll_with_seed(ast: ast::Root, seed: u64) -> RollResult {
+    roll_with(ast, RollContext::seeded(seed))
+}
+
 
 #[derive(Debug)]
 pub struct RollResult {
-    ast: ast::Root,
     expr: Expression,
     db: Database,
+    text: Arc<str>,
 }
 
 impl RollResult {
-    pub fn total(&mut self) -> i64 {
-        self.expr.total(&mut self.db)
+    fn new(ast: ast::Root, ctx: RollContext) -> Self {
+        Program::compile(ast).roll(ctx)
+    }
+
+    /// The total of the roll, or why it has none, like when it divides by zero.
+    pub fn total(&self) -> Result<i64, RollError> {
+        self.expr.total(&self.db)
+    }
+
+    /// Breaks the roll down into the expression as it was written, each die's roll, and the
+    /// total, like `4d6kh3 (6, 5, ~~2~~, 4) + 2 = 17`. See `Renderer` for the ways it can be
+    /// formatted.
+    pub fn breakdown(&self) -> Breakdown {
+        Breakdown {
+            parts: self.expr.breakdown(&self.db),
+            total: self.expr.total(&self.db),
+        }
+    }
+
+    /// Records the roll for storage. With the `serde` feature, a `RollResult` serializes as its
+    /// record.
+    pub fn record(&self) -> RollRecord {
+        RollRecord {
+            version: SCHEMA_VERSION,
+            expression: self.text.to_string(),
+            total: self.expr.total(&self.db).ok(),
+            root: NodeRecord::new(self.root()),
+        }
+    }
+
+    /// A read-only view of the rolled expression, for walking it node by node.
+    pub fn root(&self) -> NodeView<'_> {
+        NodeView::new(&self.expr, &self.db)
+    }
+
+    /// The innermost node written over `offset` in the source text, like the dice under a
+    /// cursor, or `None` if `offset` is outside every node.
+    pub fn node_at(&self, offset: TextSize) -> Option<NodeView<'_>> {
+        // Nodes come after the nodes they are part of, and siblings don't overlap.
+        self.root()
+            .descendants()
+            .filter(|node| node.range().is_some_and(|range| range.contains(offset)))
+            .last()
+    }
+
+    /// Every die drawn while rolling the expression, in order. Pass it to `replay` to check the
+    /// roll later.
+    pub fn log(&self) -> &RollLog {
+        &self.db.log
+    }
+
+    /// Whether any die counting towards the total rolled a critical success.
+    pub fn is_crit(&self) -> bool {
+        self.kept_dice().iter().any(|die| die.is_crit())
+    }
+
+    /// Whether any die counting towards the total rolled a critical failure.
+    pub fn is_fumble(&self) -> bool {
+        self.kept_dice().iter().any(|die| die.is_fumble())
+    }
+
+    fn kept_dice(&self) -> Vec<&Die> {
+        let mut dice = Vec::new();
+        self.expr.kept_dice(&self.db, &mut dice);
+
+        dice
+    }
+
+    /// The label of the band that the total falls into, or `None` if the expression isn't
+    /// banded or no band matches. Fails if the total does.
+    pub fn band(&self) -> Result<Option<&str>, RollError> {
+        match &self.expr.expr {
+            Expr::Band(band) => band.label(&self.db),
+            _ => Ok(None),
+        }
+    }
+
+    /// The outcome of a `vs` roll, or `None` if the expression isn't one. Fails if either side's
+    /// total does.
+    pub fn versus(&self, tie_policy: TiePolicy) -> Result<Option<VersusOutcome>, RollError> {
+        match &self.expr.expr {
+            Expr::Versus(versus) => versus.outcome(&self.db, tie_policy).map(Some),
+            _ => Ok(None),
+        }
+    }
+
+    pub fn tagged_total(&self) -> Result<TaggedTotal, RollError> {
+        Ok(TaggedTotal {
+            total: self.total()?,
+            tags: self.expr.tags(&self.db)?,
+        })
+    }
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for RollResult {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.record().serialize(serializer)
     }
 }
 
 impl From<ast::Root> for RollResult {
     fn from(ast: ast::Root) -> Self {
-        let mut db = Database::default();
-        let expr = db.lower_expr(ast.expr());
+        Self::new(ast, RollContext::default())
+    }
+}
+
+
+/// Subtotals of a roll, keyed by damage type tag (`2d6[fire]`).
+pub type TagTotals = BTreeMap<String, i64>;
+
+
+/// The grand total of a roll alongside the subtotal of each of its tags.
+///
+/// Untagged parts of an expression count towards `total` but not towards any entry of `tags`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TaggedTotal {
+    pub total: i64,
+    pub tags: TagTotals,
+}
+
 
-        Self { ast, expr, db }
+/// Why a roll has no total.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum RollError {
+    /// The expression divided by zero, like `1d6 / (1d2 - 1)` when the d2 rolls a 1.
+    DivisionByZero,
+    /// A total, or part of one, was too large or too small for an `i64`.
+    Overflow,
+}
+
+impl fmt::Display for RollError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::DivisionByZero => write!(f, "division by zero"),
+            Self::Overflow => write!(f, "total out of range"),
+        }
     }
 }
 
 
-#[derive(Debug, PartialEq)]
-struct RollContext {
-    rng: StdRng,
+/// The outcome of an opposed roll, like `1d20+5 vs 1d20+3`.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct VersusOutcome {
+    pub lhs: i64,
+    pub rhs: i64,
+    pub winner: Winner,
+    /// How far apart the two sides' totals are.
+    pub margin: u64,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Winner {
+    Lhs,
+    Rhs,
+    Tie,
+}
+
+/// Who wins an opposed roll whose two sides total the same.
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub enum TiePolicy {
+    /// Neither side wins.
+    #[default]
+    Tie,
+    /// The left-hand side wins, like an attacker who only needs to meet the defence.
+    LhsWins,
+    /// The right-hand side wins, like a defender who holds on a tie.
+    RhsWins,
+}
+
+
+/// A rule for rolling a pool of zero dice, like `0d6`, which rolls whatever dice it likes into
+/// the pool. The pool totals whichever of them it doesn't drop.
+///
+/// Any `Fn(&mut ZeroDicePool)` is a rule, and `ZeroDicePolicy` has some ready-made ones.
+pub trait ZeroDiceRule {
+    fn roll(&self, pool: &mut ZeroDicePool<'_>);
+}
+
+impl<F: Fn(&mut ZeroDicePool<'_>)> ZeroDiceRule for F {
+    fn roll(&self, pool: &mut ZeroDicePool<'_>) {
+        self(pool)
+    }
+}
+
+/// Common rules for rolling a pool of zero dice.
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub enum ZeroDicePolicy {
+    /// Roll nothing, so the pool totals 0.
+    #[default]
+    Empty,
+    /// Roll this many dice instead and keep the lowest, as in Blades in the Dark.
+    KeepLowest(u64),
+}
+
+impl ZeroDiceRule for ZeroDicePolicy {
+    fn roll(&self, pool: &mut ZeroDicePool<'_>) {
+        match *self {
+            Self::Empty => {}
+            Self::KeepLowest(n) => {
+                let values: Vec<_> = (0..n).map(|_| pool.roll()).collect();
+                let lowest = values.iter().enumerate().min_by_key(|(_, value)| **value);
+
+                if let Some((lowest, _)) = lowest {
+                    (0..values.len()).filter(|i| *i != lowest).for_each(|i| pool.drop(i));
+                }
+            }
+        }
+    }
+}
+
+/// Everything that decides how an expression is rolled, starting with where the dice come from.
+pub struct RollContext {
+    rng: Box<dyn DiceRng>,
+    zero_dice: Rc<dyn ZeroDiceRule>,
 }
 
 impl RollContext {
-    fn new(rng: StdRng) -> Self {
-        Self { rng }
+    pub fn new(rng: impl DiceRng + 'static) -> Self {
+        Self { rng: Box::new(rng), zero_dice: Rc::new(ZeroDicePolicy::default()) }
     }
 
+    /// A context whose dice are a pseudorandom sequence determined by `seed`.
+    ///
+    /// The sequence comes from a ChaCha12 generator seeded with `SeedableRng::seed_from_u64`,
+    /// and each die is sampled from it uniformly, in the order the expression rolls them: left
+    /// to right, with rerolls and explosions as they happen. All of these are portable, and the
+    /// tests pin the values they produce, so a given seed rolls the same dice across runs,
+    /// platforms, and releases of this crate.
+    pub fn seeded(seed: u64) -> Self {
+        Self::new(ChaCha12Rng::seed_from_u64(seed))
+    }
+
+    /// Rolls pools of zero dice with `rule`, which is `ZeroDicePolicy::Empty` by default.
+    pub fn with_zero_dice(self, rule: impl ZeroDiceRule + 'static) -> Self {
+        Self { zero_dice: Rc::new(rule), ..self }
+    }
+
+    /// Rolls a single die. Every die in a roll goes through here, one at a time, so each draw
+    /// advances the same stream, and the same seed always gives the same sequence of rolls.
     fn roll(&mut self, sides: u64) -> u64 {
-        self.rng.gen_range(1..=sides)
+        self.rng.roll(sides)
     }
+}
 
-    fn roll_many(&mut self, sides: u64, count: u64) -> impl Iterator<Item=u64> {
-        rand::distributions::Uniform::new_inclusive(1, sides)
-            .sample_iter(self.rng.clone())
-            .take(count as usize)
+impl fmt::Debug for RollContext {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RollContext").finish_non_exhaustive()
     }
 }
 
@@ -68,7 +337,11 @@ impl Default for RollContext {
 
 
 trait Total {
-    fn total(&self, db: &Database) -> i64;
+    fn total(&self, db: &Database) -> Result<i64, RollError>;
+}
+
+trait Tags {
+    fn tags(&self, db: &Database) -> Result<TagTotals, RollError>;
 }
 
 
@@ -0,0 +1,237 @@
+use crate::{roll_with, DiceRng, RollContext, RollResult, ZeroDicePolicy, ZeroDiceRule};
+use std::fmt;
+use text_size::TextRange;
+
+
+/// A single die roll made while rolling an expression.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Draw {
+    /// Which die the draw was for, counting dice in the order they were first rolled. A die
+    /// that is rerolled makes several draws.
+    pub die: usize,
+    pub sides: u64,
+    pub value: u64,
+    /// The range of the dice expression that the die belongs to.
+    pub range: TextRange,
+}
+
+
+/// Every draw made while rolling an expression, in the order they were made.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RollLog {
+    draws: Vec<Draw>,
+}
+
+impl RollLog {
+    pub fn draws(&self) -> &[Draw] {
+        &self.draws
+    }
+
+    pub(crate) fn push(&mut self, draw: Draw) {
+        self.draws.push(draw);
+    }
+}
+
+
+/// Rolls `ast` again, taking each die's value from `log` instead of a random number generator.
+///
+/// The replay fails unless it makes exactly the draws in `log`, for the same dice, in the same
+/// order, and with the same values. A successful replay therefore proves that `log` is a
+/// faithful record of rolling `ast`.
+///
+/// Pools of zero dice are rolled with the default `ZeroDicePolicy`. Use `replay_with` to replay
+/// a roll that was made with another rule.
+pub fn replay(ast: ast::Root, log: &RollLog) -> Result<RollResult, ReplayError> {
+    replay_with(ast, log, ZeroDicePolicy::default())
+}
+
+/// Like `replay`, but rolls pools of zero dice with `zero_dice`, which must be the rule the roll
+/// was made with for the replay to match.
+pub fn replay_with(ast: ast::Root, log: &RollLog,
+                   zero_dice: impl ZeroDiceRule + 'static) -> Result<RollResult, ReplayError> {
+    let rng = ReplayRolls { values: log.draws.iter().map(|draw| draw.value).collect(), next: 0 };
+    let result = roll_with(ast, RollContext::new(rng).with_zero_dice(zero_dice));
+
+    let (expected, found) = (log.draws(), result.log().draws());
+
+    if let Some(index) = expected.iter().zip(found).position(|(expected, found)| expected != found) {
+        Err(ReplayError::Mismatch { index })
+    } else if found.len() > expected.len() {
+        Err(ReplayError::TooFewDraws)
+    } else if found.len() < expected.len() {
+        Err(ReplayError::TooManyDraws)
+    } else {
+        Ok(result)
+    }
+}
+
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ReplayError {
+    /// The expression rolled more dice than the log has draws for.
+    TooFewDraws,
+    /// The log has draws left over after the expression was rolled.
+    TooManyDraws,
+    /// The draw at `index` differs from the log's: it is for another die, or its value doesn't
+    /// fit the die.
+    Mismatch { index: usize },
+}
+
+impl fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::TooFewDraws => write!(f, "the log has too few draws for the expression"),
+            Self::TooManyDraws => write!(f, "the log has more draws than the expression rolls"),
+            Self::Mismatch { index } => write!(f, "draw {} does not match the log", index),
+        }
+    }
+}
+
+
+/// Hands out a log's values in order. Once they run out, or when a value can't be shown by the
+/// die being rolled, it rolls a 1 and leaves `replay` to spot the difference.
+struct ReplayRolls {
+    values: Vec<u64>,
+    next: usize,
+}
+
+impl DiceRng for ReplayRolls {
+    fn roll(&mut self, sides: u64) -> u64 {
+        let value = self.values.get(self.next).copied();
+        self.next += 1;
+
+        value.filter(|value| (1..=sides).contains(value)).unwrap_or(1)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{roll_with_seed, ZeroDicePool, SEED};
+
+    fn parse(input: &str) -> ast::Root {
+        ast::Root::cast(parser::parse(input).syntax()).unwrap()
+    }
+
+    fn range(start: u32, end: u32) -> TextRange {
+        TextRange::new(start.into(), end.into())
+    }
+
+    fn log(draws: Vec<Draw>) -> RollLog {
+        RollLog { draws }
+    }
+
+    #[test]
+    fn log_records_every_draw() {
+        // [5, 15, 16, 17]
+        let result = roll_with_seed(parse("2d20ro<10 + 1d20"), SEED);
+
+        assert_eq!(result.log(), &log(vec![
+            Draw { die: 0, sides: 20, value: 5, range: range(0, 9) },
+            Draw { die: 1, sides: 20, value: 15, range: range(0, 9) },
+            Draw { die: 0, sides: 20, value: 16, range: range(0, 9) },
+            Draw { die: 2, sides: 20, value: 17, range: range(12, 16) },
+        ]));
+    }
+
+    #[test]
+    fn log_records_explosions_as_new_dice() {
+        // [5, 15, 16, 17, 5]
+        let result = roll_with_seed(parse("2d20e>10"), SEED);
+        let dice: Vec<_> = result.log().draws().iter().map(|draw| draw.die).collect();
+
+        assert_eq!(dice, vec![0, 1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn replay_reproduces_roll() {
+        let input = "4d6kh3 + 2d20ro<10 - (1d8, 1d8)kl1";
+        let original = roll_with_seed(parse(input), SEED);
+        let replayed = replay(parse(input), original.log()).unwrap();
+
+        assert_eq!(replayed.log(), original.log());
+        assert_eq!(replayed.total(), original.total());
+    }
+
+    #[test]
+    fn replay_with_the_zero_dice_rule_of_the_roll() {
+        let input = "0d6 + 1d6";
+        let ctx = RollContext::seeded(SEED).with_zero_dice(ZeroDicePolicy::KeepLowest(2));
+        let original = roll_with(parse(input), ctx);
+
+        let replayed = replay_with(parse(input), original.log(), ZeroDicePolicy::KeepLowest(2));
+        assert_eq!(replayed.unwrap().total(), original.total());
+
+        assert_eq!(
+            replay(parse(input), original.log()).err(),
+            Some(ReplayError::Mismatch { index: 0 }),
+        );
+    }
+
+    #[test]
+    fn replay_with_a_custom_zero_dice_rule() {
+        // Roll one die and keep it.
+        let rule = |pool: &mut ZeroDicePool<'_>| {
+            pool.roll();
+        };
+        let original = roll_with(parse("0d20"), RollContext::seeded(SEED).with_zero_dice(rule));
+
+        let replayed = replay_with(parse("0d20"), original.log(), rule);
+        assert_eq!(replayed.unwrap().total(), Ok(5));
+    }
+
+    #[test]
+    fn replay_uses_logged_values() {
+        let original = roll_with_seed(parse("3d20"), SEED);
+
+        let mut draws = original.log().draws().to_vec();
+        draws[1].value = 20;
+
+        let replayed = replay(parse("3d20"), &log(draws)).unwrap();
+        assert_eq!(replayed.total(), Ok(5 + 20 + 16));
+    }
+
+    #[test]
+    fn replay_rejects_impossible_value() {
+        let original = roll_with_seed(parse("3d6"), SEED);
+
+        let mut draws = original.log().draws().to_vec();
+        draws[2].value = 7;
+
+        assert_eq!(
+            replay(parse("3d6"), &log(draws)).err(),
+            Some(ReplayError::Mismatch { index: 2 }),
+        );
+    }
+
+    #[test]
+    fn replay_rejects_other_expression() {
+        let original = roll_with_seed(parse("2d6"), SEED);
+
+        assert_eq!(
+            replay(parse("2d8"), original.log()).err(),
+            Some(ReplayError::Mismatch { index: 0 }),
+        );
+    }
+
+    #[test]
+    fn replay_rejects_short_log() {
+        let original = roll_with_seed(parse("2d6"), SEED);
+
+        assert_eq!(
+            replay(parse("3d6"), original.log()).err(),
+            Some(ReplayError::TooFewDraws),
+        );
+    }
+
+    #[test]
+    fn replay_rejects_long_log() {
+        let original = roll_with_seed(parse("3d6"), SEED);
+
+        assert_eq!(
+            replay(parse("2d6"), original.log()).err(),
+            Some(ReplayError::TooManyDraws),
+        );
+    }
+}
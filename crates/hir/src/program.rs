@@ -0,0 +1,281 @@
+use super::*;
+use la_arena::{Arena, ArenaMap, Idx};
+use std::convert::TryFrom;
+use std::ops::Index;
+use std::sync::Arc;
+use syntax::SyntaxKind;
+use text_size::TextRange;
+
+
+pub(crate) type NodeIdx = Idx<Node>;
+
+
+/// An expression that has been compiled but not rolled.
+///
+/// Compiling does all the work of reading the syntax tree up front, so a program can be rolled
+/// any number of times without parsing it again. Programs are plain data, so they can be cloned
+/// and shared between threads.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Program {
+    nodes: Nodes,
+    root: NodeIdx,
+    text: Arc<str>,
+}
+
+impl Program {
+    pub fn compile(ast: ast::Root) -> Self {
+        let mut nodes = Nodes::default();
+        let root = lower_expr(&mut nodes, ast.expr());
+
+        Self { nodes, root, text: ast.text().into() }
+    }
+
+    pub fn roll(&self, ctx: RollContext) -> RollResult {
+        let mut db = Database::new(ctx);
+        let expr = db.lower(self);
+
+        RollResult { expr, db, text: self.text.clone() }
+    }
+
+    /// The source text the program was compiled from.
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    pub(crate) fn root(&self) -> NodeIdx {
+        self.root
+    }
+
+    /// The range of a node in the source text, or `None` if it is missing from the source.
+    pub(crate) fn range(&self, idx: NodeIdx) -> Option<TextRange> {
+        self.nodes.ranges.get(idx).copied()
+    }
+}
+
+impl Index<NodeIdx> for Program {
+    type Output = Node;
+
+    fn index(&self, idx: NodeIdx) -> &Node {
+        &self.nodes.arena[idx]
+    }
+}
+
+
+/// The nodes of a program, alongside the range of each in the source text.
+#[derive(Debug, Clone, Default, PartialEq)]
+struct Nodes {
+    arena: Arena<Node>,
+    ranges: ArenaMap<NodeIdx, TextRange>,
+}
+
+
+/// An unrolled expression, mirroring `Expr`. Dice keep their count, sides, and operations, and
+/// are only rolled when the program is.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Node {
+    Missing,
+    Band { expr: NodeIdx, bands: Vec<BandArm> },
+    Binary { op: BinaryOp, lhs: NodeIdx, rhs: NodeIdx },
+    Dice { count: Option<u64>, sides: Option<u64>, ops: Vec<SetOperation>, range: TextRange },
+    Literal(Option<u64>),
+    Set { items: Vec<NodeIdx>, ops: Vec<SetOperation> },
+    Tagged { tag: String, expr: NodeIdx },
+    Unary { op: UnaryOp, expr: NodeIdx },
+    Versus { lhs: NodeIdx, rhs: NodeIdx },
+}
+
+
+fn lower_expr(nodes: &mut Nodes, ast: Option<ast::Expr>) -> NodeIdx {
+    let range = ast.as_ref().map(ast::Expr::range);
+
+    let node = if let Some(ast) = ast {
+        match ast {
+            ast::Expr::BandExpr(ast) => lower_band(nodes, ast),
+            ast::Expr::BinaryExpr(ast) => lower_binary(nodes, ast),
+            ast::Expr::Dice(ast) => lower_dice(ast),
+            ast::Expr::Literal(ast) => Node::Literal(ast.parse()),
+            ast::Expr::ParenExpr(ast) => return lower_expr(nodes, ast.expr()),
+            ast::Expr::Set(ast) => lower_set(nodes, ast),
+            ast::Expr::TaggedExpr(ast) => lower_tagged(nodes, ast),
+            ast::Expr::UnaryExpr(ast) => lower_unary(nodes, ast),
+        }
+    } else {
+        Node::Missing
+    };
+
+    let idx = nodes.arena.alloc(node);
+
+    if let Some(range) = range {
+        nodes.ranges.insert(idx, range);
+    }
+
+    idx
+}
+
+fn lower_band(nodes: &mut Nodes, ast: ast::BandExpr) -> Node {
+    let expr = lower_expr(nodes, ast.expr());
+    let bands = ast.bands().filter_map(lower_band_arm).collect();
+
+    Node::Band { expr, bands }
+}
+
+/// Lowers a band into its inclusive bounds. Bands that failed to parse or validate are left out,
+/// since they have already been reported, and so are bands that no total could fall into.
+fn lower_band_arm(ast: ast::Band) -> Option<BandArm> {
+    let label = ast.label()?;
+
+    // A bound too large for an `i64` is `None`, since no total can reach it.
+    let bounds: Option<Vec<Option<i64>>> = ast.bounds()
+        .map(|bound| bound.parse().map(|n| i64::try_from(n).ok()))
+        .collect();
+    let bounds = bounds?;
+
+    let (min, max) = match (ast.op().map(|op| op.kind()), bounds.as_slice()) {
+        (None, &[n]) => (Some(n?), Some(n?)),
+        (Some(SyntaxKind::Less), &[Some(n)]) => (None, Some(n.checked_sub(1)?)),
+        (Some(SyntaxKind::Less), &[None]) => (None, None),
+        (Some(SyntaxKind::LessEq), &[n]) => (None, n),
+        (Some(SyntaxKind::Greater), &[n]) => (Some(n?.checked_add(1)?), None),
+        (Some(SyntaxKind::GreaterEq), &[n]) => (Some(n?), None),
+        (Some(SyntaxKind::DotDot), &[min, max]) => (Some(min?), max),
+        _ => return None,
+    };
+
+    Some(BandArm::new(min, max, label))
+}
+
+fn lower_binary(nodes: &mut Nodes, ast: ast::BinaryExpr) -> Node {
+    let op = match ast.op().unwrap().kind() {
+        SyntaxKind::Plus => Some(BinaryOp::Add),
+        SyntaxKind::Minus => Some(BinaryOp::Sub),
+        SyntaxKind::Star => Some(BinaryOp::Mul),
+        SyntaxKind::Slash => Some(BinaryOp::Div),
+        SyntaxKind::Versus => None,
+        _ => unreachable!(),
+    };
+
+    let lhs = lower_expr(nodes, ast.lhs());
+    let rhs = lower_expr(nodes, ast.rhs());
+
+    match op {
+        Some(op) => Node::Binary { op, lhs, rhs },
+        None => Node::Versus { lhs, rhs },
+    }
+}
+
+fn lower_dice(ast: ast::Dice) -> Node {
+    let ops = ast.ops().map(lower_set_op).collect();
+
+    Node::Dice { count: ast.count(), sides: ast.sides(), ops, range: ast.range() }
+}
+
+fn lower_set(nodes: &mut Nodes, ast: ast::Set) -> Node {
+    let items = ast.items()
+        .map(|item| lower_expr(nodes, Some(item)))
+        .collect();
+
+    let ops = ast.ops().map(lower_set_op).collect();
+
+    Node::Set { items, ops }
+}
+
+fn lower_tagged(nodes: &mut Nodes, ast: ast::TaggedExpr) -> Node {
+    let tag = ast.tag().unwrap();
+    let expr = lower_expr(nodes, ast.expr());
+
+    Node::Tagged { tag, expr }
+}
+
+fn lower_unary(nodes: &mut Nodes, ast: ast::UnaryExpr) -> Node {
+    let op = match ast.op().unwrap().kind() {
+        SyntaxKind::Minus => UnaryOp::Neg,
+        _ => unreachable!(),
+    };
+
+    let expr = lower_expr(nodes, ast.expr());
+
+    Node::Unary { op, expr }
+}
+
+fn lower_set_op(ast: ast::SetOp) -> SetOperation {
+    let op = match ast.op().unwrap().kind() {
+        SyntaxKind::Keep => SetOp::Keep,
+        SyntaxKind::Drop => SetOp::Drop,
+        SyntaxKind::Reroll => SetOp::Reroll,
+        SyntaxKind::RerollOnce => SetOp::RerollOnce,
+        SyntaxKind::RerollAdd => SetOp::RerollAdd,
+        SyntaxKind::Explode => SetOp::Explode,
+        SyntaxKind::Min => SetOp::Min,
+        SyntaxKind::Max => SetOp::Max,
+        SyntaxKind::CritSuccess => SetOp::CritSuccess,
+        SyntaxKind::CritFail => SetOp::CritFail,
+        _ => unreachable!(),
+    };
+
+    let sel = ast.sel()
+        .map_or(SetSel::Number,
+                |token| match token.kind() {
+                    SyntaxKind::Highest => SetSel::Highest,
+                    SyntaxKind::Lowest => SetSel::Lowest,
+                    SyntaxKind::Greater => SetSel::Greater,
+                    SyntaxKind::Less => SetSel::Less,
+                    _ => unreachable!(),
+                });
+
+    SetOperation::new(op, sel, ast.num())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::SEED;
+
+    fn parse(input: &str) -> ast::Root {
+        ast::Root::cast(parser::parse(input).syntax()).unwrap()
+    }
+
+    #[test]
+    fn programs_can_be_shared_between_threads() {
+        fn assert_send_sync<T: Send + Sync>() {}
+
+        assert_send_sync::<Program>();
+    }
+
+    #[test]
+    fn parens_are_compiled_away() {
+        assert_eq!(Program::compile(parse("((1))")).nodes.arena.len(), 1);
+    }
+
+    #[test]
+    fn rolling_a_program_matches_rolling_its_ast() {
+        let input = "4d6kh3 + (2d20, 5)kl1 - 1d8e8[fire]";
+        let program = Program::compile(parse(input));
+
+        let compiled = program.roll(RollContext::seeded(SEED));
+        let direct = roll_with_seed(parse(input), SEED);
+
+        assert_eq!(compiled.log(), direct.log());
+        assert_eq!(compiled.tagged_total(), direct.tagged_total());
+    }
+
+    #[test]
+    fn set_ops_without_a_number_are_skipped() {
+        let program = Program::compile(parse("4d6k + 1"));
+
+        let result = program.roll(RollContext::new(FixedRolls::new(vec![1, 2, 3, 4])));
+
+        assert_eq!(result.total(), Ok(11));
+        assert_eq!(program.explain(), "roll four six-sided dice, keep only dice that show ?, add 1");
+    }
+
+    #[test]
+    fn programs_can_be_rolled_repeatedly() {
+        let program = Program::compile(parse("10d20"));
+
+        let first = program.roll(RollContext::seeded(1));
+        let second = program.roll(RollContext::seeded(2));
+
+        assert_ne!(first.log(), second.log());
+    }
+}
@@ -0,0 +1,293 @@
+use crate::{DieView, NodeView, NodeViewKind};
+use text_size::TextRange;
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+
+/// The version of the format that `RollRecord` serializes as. It is bumped whenever a field is
+/// renamed, removed, or changes meaning; fields may be added without bumping it.
+///
+/// Version 1 serializes as JSON like this, for `1d20 + 2` rolling a 15:
+///
+/// ```json
+/// {
+///   "version": 1,
+///   "expression": "1d20 + 2",
+///   "total": 17,
+///   "root": {
+///     "range": [0, 8], "total": 17, "kept": true, "kind": "binary", "op": "+",
+///     "lhs": {
+///       "range": [0, 4], "total": 15, "kept": true, "kind": "dice",
+///       "count": 1, "sides": 20, "ops": "",
+///       "dice": [{ "sides": 20, "values": [15], "kept": true, "exploded": false,
+///                  "rerolled": false, "crit": false, "fumble": false }]
+///     },
+///     "rhs": { "range": [7, 8], "total": 2, "kept": true, "kind": "literal", "value": 2 }
+///   }
+/// }
+/// ```
+///
+/// Ranges are byte offsets into `expression`, and are `null` for parts of the expression that
+/// are missing from it.
+pub const SCHEMA_VERSION: u32 = 1;
+
+
+/// Everything about a roll that is worth storing: what was rolled, how each part of it came out,
+/// and the total. See `RollResult::record`.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct RollRecord {
+    /// The `SCHEMA_VERSION` the record was made with.
+    pub version: u32,
+    /// The source text of the expression, exactly as it was written.
+    pub expression: String,
+    /// The total of the roll, or `None` if it has none, like when it divides by zero.
+    pub total: Option<i64>,
+    pub root: NodeRecord,
+}
+
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct NodeRecord {
+    pub range: Option<TextRange>,
+    /// The total of the node, whether or not it counts towards the total of its parent, or
+    /// `None` if it has none.
+    pub total: Option<i64>,
+    /// Whether the node counts towards the total of its parent, which it doesn't if it was
+    /// dropped from a set.
+    pub kept: bool,
+    #[cfg_attr(feature = "serde", serde(flatten))]
+    pub kind: NodeKind,
+}
+
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(tag = "kind", rename_all = "snake_case"))]
+pub enum NodeKind {
+    Missing,
+    Band {
+        /// The label of the band the total fell into, if any.
+        label: Option<String>,
+        expr: Box<NodeRecord>,
+    },
+    Binary {
+        /// `+`, `-`, `*` or `/`.
+        op: String,
+        lhs: Box<NodeRecord>,
+        rhs: Box<NodeRecord>,
+    },
+    Dice {
+        count: Option<u64>,
+        sides: Option<u64>,
+        /// The operations applied to the dice, as they are written, like `kh3`.
+        ops: String,
+        dice: Vec<DieRecord>,
+    },
+    Literal {
+        value: Option<u64>,
+    },
+    Set {
+        items: Vec<NodeRecord>,
+        /// The operations applied to the set, as they are written, like `kh1`.
+        ops: String,
+    },
+    Tagged {
+        tag: String,
+        expr: Box<NodeRecord>,
+    },
+    Unary {
+        /// `-`.
+        op: String,
+        expr: Box<NodeRecord>,
+    },
+    Versus {
+        lhs: Box<NodeRecord>,
+        rhs: Box<NodeRecord>,
+    },
+}
+
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct DieRecord {
+    pub sides: u64,
+    /// Every value the die rolled, in order. All but the last were rerolled.
+    pub values: Vec<i64>,
+    pub kept: bool,
+    /// Whether the die exploded, adding another die to its pool.
+    pub exploded: bool,
+    pub rerolled: bool,
+    pub crit: bool,
+    pub fumble: bool,
+}
+
+
+impl NodeRecord {
+    pub(crate) fn new(node: NodeView<'_>) -> Self {
+        let child = |node| Box::new(Self::new(node));
+        let mut children = node.children();
+
+        let kind = match node.kind() {
+            NodeViewKind::Missing => NodeKind::Missing,
+            NodeViewKind::Band { label } => NodeKind::Band {
+                label: label.map(str::to_string),
+                expr: child(children.next().unwrap()),
+            },
+            NodeViewKind::Binary { op } => NodeKind::Binary {
+                op: op.to_string(),
+                lhs: child(children.next().unwrap()),
+                rhs: child(children.next().unwrap()),
+            },
+            NodeViewKind::Dice { count, sides } => NodeKind::Dice {
+                count,
+                sides,
+                ops: node.ops(),
+                dice: node.dice().map(DieRecord::new).collect(),
+            },
+            NodeViewKind::Literal { value } => NodeKind::Literal { value },
+            NodeViewKind::Set => NodeKind::Set {
+                items: children.map(Self::new).collect(),
+                ops: node.ops(),
+            },
+            NodeViewKind::Tagged { tag } => NodeKind::Tagged {
+                tag: tag.to_string(),
+                expr: child(children.next().unwrap()),
+            },
+            NodeViewKind::Unary { op } => NodeKind::Unary {
+                op: op.to_string(),
+                expr: child(children.next().unwrap()),
+            },
+            NodeViewKind::Versus => NodeKind::Versus {
+                lhs: child(children.next().unwrap()),
+                rhs: child(children.next().unwrap()),
+            },
+        };
+
+        Self {
+            range: node.range(),
+            total: node.total().ok(),
+            kept: node.is_kept(),
+            kind,
+        }
+    }
+}
+
+impl DieRecord {
+    fn new(die: DieView<'_>) -> Self {
+        Self {
+            sides: die.sides(),
+            values: die.values(),
+            kept: die.is_kept(),
+            exploded: die.is_exploded(),
+            rerolled: die.is_rerolled(),
+            crit: die.is_crit(),
+            fumble: die.is_fumble(),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{roll_with, FixedRolls, RollContext};
+
+    fn record(input: &str, rolls: &[u64]) -> RollRecord {
+        let root = ast::Root::cast(parser::parse(input).syntax()).unwrap();
+
+        roll_with(root, RollContext::new(FixedRolls::new(rolls.iter().copied()))).record()
+    }
+
+    fn range(start: u32, end: u32) -> Option<TextRange> {
+        Some(TextRange::new(start.into(), end.into()))
+    }
+
+    #[test]
+    fn records_the_expression_and_total() {
+        let record = record("1d20 + 2", &[15]);
+
+        assert_eq!(record.version, SCHEMA_VERSION);
+        assert_eq!(record.expression, "1d20 + 2");
+        assert_eq!(record.total, Some(17));
+        assert_eq!(record.root.range, range(0, 8));
+    }
+
+    #[test]
+    fn records_dice() {
+        let record = record("3d6rr1kh2", &[1, 4, 6, 2]);
+
+        let die = |values: Vec<i64>, kept, rerolled, crit| DieRecord {
+            sides: 6, values, kept, exploded: false, rerolled, crit, fumble: false,
+        };
+
+        assert_eq!(record.root, NodeRecord {
+            range: range(0, 9),
+            total: Some(10),
+            kept: true,
+            kind: NodeKind::Dice {
+                count: Some(3),
+                sides: Some(6),
+                ops: "rr1kh2".to_string(),
+                dice: vec![
+                    die(vec![1, 2], false, true, false),
+                    die(vec![4], true, false, false),
+                    die(vec![6], true, false, false),
+                ],
+            },
+        });
+    }
+
+    #[test]
+    fn records_dropped_items_and_missing_ranges() {
+        let record = record("(1d4, 3)kh1 + ", &[2]);
+
+        let items = match record.root.kind {
+            NodeKind::Binary { lhs, rhs, .. } => {
+                assert_eq!(rhs.range, None);
+                assert_eq!(rhs.kind, NodeKind::Missing);
+
+                match lhs.kind {
+                    NodeKind::Set { items, .. } => items,
+                    kind => panic!("expected a set, found {:?}", kind),
+                }
+            }
+            kind => panic!("expected a binary expression, found {:?}", kind),
+        };
+
+        assert_eq!((items[0].kept, items[0].total, items[0].range), (false, Some(2), range(1, 4)));
+        assert_eq!((items[1].kept, items[1].total, items[1].range), (true, Some(3), range(6, 7)));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serializes_as_json() {
+        let root = ast::Root::cast(parser::parse("1d20 + 2").syntax()).unwrap();
+        let result = roll_with(root, RollContext::new(FixedRolls::new(vec![15])));
+
+        let json = serde_json::to_value(&result).unwrap();
+
+        assert_eq!(json, serde_json::json!({
+            "version": 1,
+            "expression": "1d20 + 2",
+            "total": 17,
+            "root": {
+                "range": [0, 8], "total": 17, "kept": true, "kind": "binary", "op": "+",
+                "lhs": {
+                    "range": [0, 4], "total": 15, "kept": true, "kind": "dice",
+                    "count": 1, "sides": 20, "ops": "",
+                    "dice": [{
+                        "sides": 20, "values": [15], "kept": true, "exploded": false,
+                        "rerolled": false, "crit": false, "fumble": false,
+                    }],
+                },
+                "rhs": { "range": [7, 8], "total": 2, "kept": true, "kind": "literal", "value": 2 },
+            },
+        }));
+
+        let record: RollRecord = serde_json::from_value(json).unwrap();
+        assert_eq!(record, result.record());
+    }
+}
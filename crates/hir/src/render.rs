@@ -0,0 +1,373 @@
+use crate::RollError;
+use std::fmt;
+
+
+/// A roll broken down for display: the expression as it was written with the roll of each die
+/// after its dice, and its total. See `RollResult::breakdown`.
+///
+/// Its `Display` implementation formats it as Markdown.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Breakdown {
+    pub parts: Vec<Part>,
+    pub total: Result<i64, RollError>,
+}
+
+impl Breakdown {
+    pub fn render(&self, renderer: &mut impl Renderer) -> String {
+        let mut out = String::new();
+        renderer.parts(&self.parts, &mut out);
+
+        match self.total {
+            Ok(total) => renderer.total(total, &mut out),
+            Err(error) => renderer.error(error, &mut out),
+        }
+
+        out
+    }
+}
+
+impl fmt::Display for Breakdown {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.render(&mut Markdown::default()))
+    }
+}
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Part {
+    /// Dice notation, operators, numbers and punctuation.
+    Text(String),
+    Roll(DieRoll),
+    /// An item dropped from a set, like the `5` of `(1d20, 5)kh1` when the d20 rolls higher.
+    Dropped(Vec<Part>),
+}
+
+
+/// One value rolled by a die. A die that was rerolled has a roll for each of its values.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct DieRoll {
+    pub value: i64,
+    /// Whether the die was dropped from its pool.
+    pub dropped: bool,
+    /// Whether the die was rolled again, replacing this value.
+    pub rerolled: bool,
+    pub exploded: bool,
+    pub crit: bool,
+    pub fumble: bool,
+}
+
+impl DieRoll {
+    /// Whether the value doesn't count towards the total, because it was dropped or rerolled.
+    pub fn is_discarded(&self) -> bool {
+        self.dropped || self.rerolled
+    }
+}
+
+impl fmt::Display for DieRoll {
+    /// Writes the value, followed by a `!` if the die exploded.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.value)?;
+
+        if self.exploded {
+            write!(f, "!")?;
+        }
+
+        Ok(())
+    }
+}
+
+
+/// Formats a `Breakdown`, one part at a time.
+pub trait Renderer {
+    fn text(&mut self, text: &str, out: &mut String);
+
+    fn roll(&mut self, roll: &DieRoll, out: &mut String);
+
+    /// Writes a dropped item of a set. Implementations usually mark it and then write `parts`.
+    fn dropped(&mut self, parts: &[Part], out: &mut String);
+
+    fn total(&mut self, total: i64, out: &mut String) {
+        self.text(&format!(" = {}", total), out);
+    }
+
+    /// Writes why the roll has no total, in place of the total.
+    fn error(&mut self, error: RollError, out: &mut String) {
+        self.text(&format!(" = ? ({})", error), out);
+    }
+
+    fn parts(&mut self, parts: &[Part], out: &mut String) {
+        for part in parts {
+            match part {
+                Part::Text(text) => self.text(text, out),
+                Part::Roll(roll) => self.roll(roll, out),
+                Part::Dropped(parts) => self.dropped(parts, out),
+            }
+        }
+    }
+}
+
+
+/// Renders without any formatting, putting whatever doesn't count towards the total in square
+/// brackets: `4d6kh3 (6, 5, [2], 4) + 2 = 17`.
+#[derive(Debug, Default)]
+pub struct Plain;
+
+impl Renderer for Plain {
+    fn text(&mut self, text: &str, out: &mut String) {
+        out.push_str(text);
+    }
+
+    fn roll(&mut self, roll: &DieRoll, out: &mut String) {
+        if roll.is_discarded() {
+            out.push_str(&format!("[{}]", roll));
+        } else {
+            out.push_str(&roll.to_string());
+        }
+    }
+
+    fn dropped(&mut self, parts: &[Part], out: &mut String) {
+        out.push('[');
+        self.parts(parts, out);
+        out.push(']');
+    }
+}
+
+
+/// Renders with terminal colours: crits are green, fumbles are red, and whatever doesn't count
+/// towards the total is dim.
+#[derive(Debug, Default)]
+pub struct Ansi {
+    /// How many dropped items are being written, which are dim as a whole.
+    dropped: usize,
+}
+
+impl Ansi {
+    const DIM: &'static str = "\x1b[2m";
+    const GREEN: &'static str = "\x1b[32m";
+    const RED: &'static str = "\x1b[31m";
+    const RESET: &'static str = "\x1b[0m";
+}
+
+impl Renderer for Ansi {
+    fn text(&mut self, text: &str, out: &mut String) {
+        out.push_str(text);
+    }
+
+    fn roll(&mut self, roll: &DieRoll, out: &mut String) {
+        let style = if self.dropped > 0 {
+            None
+        } else if roll.is_discarded() {
+            Some(Self::DIM)
+        } else if roll.crit {
+            Some(Self::GREEN)
+        } else if roll.fumble {
+            Some(Self::RED)
+        } else {
+            None
+        };
+
+        match style {
+            Some(style) => out.push_str(&format!("{}{}{}", style, roll, Self::RESET)),
+            None => out.push_str(&roll.to_string()),
+        }
+    }
+
+    fn dropped(&mut self, parts: &[Part], out: &mut String) {
+        if self.dropped == 0 {
+            out.push_str(Self::DIM);
+        }
+
+        self.dropped += 1;
+        self.parts(parts, out);
+        self.dropped -= 1;
+
+        if self.dropped == 0 {
+            out.push_str(Self::RESET);
+        }
+    }
+}
+
+
+/// Renders as Markdown, striking through whatever doesn't count towards the total:
+/// `4d6kh3 (6, 5, ~~2~~, 4) + 2 = 17`.
+#[derive(Debug, Default)]
+pub struct Markdown {
+    /// How many dropped items are being written. Strikethrough doesn't nest, so only the
+    /// outermost is marked.
+    dropped: usize,
+}
+
+impl Renderer for Markdown {
+    fn text(&mut self, text: &str, out: &mut String) {
+        out.push_str(text);
+    }
+
+    fn roll(&mut self, roll: &DieRoll, out: &mut String) {
+        if roll.is_discarded() && self.dropped == 0 {
+            out.push_str(&format!("~~{}~~", roll));
+        } else {
+            out.push_str(&roll.to_string());
+        }
+    }
+
+    fn dropped(&mut self, parts: &[Part], out: &mut String) {
+        let outermost = self.dropped == 0;
+
+        if outermost {
+            out.push_str("~~");
+        }
+
+        self.dropped += 1;
+        self.parts(parts, out);
+        self.dropped -= 1;
+
+        if outermost {
+            out.push_str("~~");
+        }
+    }
+}
+
+
+/// Renders as HTML, escaping all text. Each roll is a `<span>` with the class `roll` and any of
+/// `dropped`, `rerolled`, `exploded`, `crit` and `fumble`; dropped items of sets are wrapped in
+/// a `<span class="dropped">`; and the total is a `<span class="total">`.
+#[derive(Debug, Default)]
+pub struct Html;
+
+impl Renderer for Html {
+    fn text(&mut self, text: &str, out: &mut String) {
+        for c in text.chars() {
+            match c {
+                '&' => out.push_str("&amp;"),
+                '<' => out.push_str("&lt;"),
+                '>' => out.push_str("&gt;"),
+                '"' => out.push_str("&quot;"),
+                '\'' => out.push_str("&#39;"),
+                c => out.push(c),
+            }
+        }
+    }
+
+    fn roll(&mut self, roll: &DieRoll, out: &mut String) {
+        let flags = [
+            (roll.dropped, "dropped"),
+            (roll.rerolled, "rerolled"),
+            (roll.exploded, "exploded"),
+            (roll.crit, "crit"),
+            (roll.fumble, "fumble"),
+        ];
+
+        out.push_str("<span class=\"roll");
+
+        for (_, class) in flags.iter().filter(|(set, _)| *set) {
+            out.push(' ');
+            out.push_str(class);
+        }
+
+        out.push_str(&format!("\">{}</span>", roll));
+    }
+
+    fn dropped(&mut self, parts: &[Part], out: &mut String) {
+        out.push_str("<span class=\"dropped\">");
+        self.parts(parts, out);
+        out.push_str("</span>");
+    }
+
+    fn total(&mut self, total: i64, out: &mut String) {
+        out.push_str(&format!(" = <span class=\"total\">{}</span>", total));
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{roll_with, FixedRolls, RollContext};
+
+    fn breakdown(input: &str, rolls: &[u64]) -> Breakdown {
+        let root = ast::Root::cast(parser::parse(input).syntax()).unwrap();
+
+        roll_with(root, RollContext::new(FixedRolls::new(rolls.iter().copied()))).breakdown()
+    }
+
+    #[test]
+    fn parts() {
+        let roll = |value, dropped| DieRoll {
+            value, dropped, rerolled: false, exploded: false, crit: false, fumble: false,
+        };
+
+        assert_eq!(breakdown("2d6kh1 + 1", &[2, 5]), Breakdown {
+            parts: vec![
+                Part::Text("2d6kh1 (".to_string()),
+                Part::Roll(roll(2, true)),
+                Part::Text(", ".to_string()),
+                Part::Roll(roll(5, false)),
+                Part::Text(") + 1".to_string()),
+            ],
+            total: Ok(6),
+        });
+    }
+
+    #[test]
+    fn plain() {
+        let breakdown = breakdown("(4d6kh3, 1d4rr1)kh1", &[6, 5, 2, 4, 1, 3]);
+
+        assert_eq!(
+            breakdown.render(&mut Plain),
+            "(4d6kh3 (6, 5, [2], 4), [1d4rr1 ([1], 3)])kh1 = 15",
+        );
+    }
+
+    #[test]
+    fn ansi() {
+        let breakdown = breakdown("3d20kh2 + (1d6, 1d6)kh1", &[20, 1, 7, 3, 5]);
+
+        assert_eq!(
+            breakdown.render(&mut Ansi::default()),
+            "3d20kh2 (\x1b[32m20\x1b[0m, \x1b[2m1\x1b[0m, 7) + (\x1b[2m1d6 (3)\x1b[0m, 1d6 (5))kh1 = 32",
+        );
+    }
+
+    #[test]
+    fn ansi_fumbles() {
+        let breakdown = breakdown("1d20", &[1]);
+
+        assert_eq!(breakdown.render(&mut Ansi::default()), "1d20 (\x1b[31m1\x1b[0m) = 1");
+    }
+
+    #[test]
+    fn markdown_doesnt_nest_strikethrough() {
+        let breakdown = breakdown("(2d6kl1, 12)kh1", &[3, 4]);
+
+        assert_eq!(breakdown.render(&mut Markdown::default()), "(~~2d6kl1 (3, 4)~~, 12)kh1 = 12");
+        assert_eq!(breakdown.to_string(), breakdown.render(&mut Markdown::default()));
+    }
+
+    #[test]
+    fn rolls_without_a_total() {
+        let breakdown = breakdown("1d6 / (1d2 - 1)", &[4, 1]);
+
+        assert_eq!(breakdown.render(&mut Plain), "1d6 (4) / (1d2 (1) - 1) = ? (division by zero)");
+    }
+
+    #[test]
+    fn html() {
+        let breakdown = breakdown("1d6e6cs6[cold] vs 2d4cf1kh1", &[6, 2, 1, 3]);
+
+        assert_eq!(
+            breakdown.render(&mut Html),
+            "1d6e6cs6 (<span class=\"roll exploded crit\">6!</span>, \
+             <span class=\"roll\">2</span>)[cold] vs 2d4cf1kh1 \
+             (<span class=\"roll dropped fumble\">1</span>, <span class=\"roll\">3</span>) \
+             = <span class=\"total\">5</span>",
+        );
+    }
+
+    #[test]
+    fn html_escapes_text() {
+        let mut out = String::new();
+        Html.text("<b>&\"'", &mut out);
+
+        assert_eq!(out, "&lt;b&gt;&amp;&quot;&#39;");
+    }
+}
@@ -0,0 +1,224 @@
+use rand::distributions::{Distribution, Uniform};
+use rand::RngCore;
+
+
+/// A source of die rolls.
+///
+/// Every random number generator from `rand` is a `DiceRng`, so a context can roll with any of
+/// them. Implement it directly to roll dice some other way.
+pub trait DiceRng {
+    /// Rolls a die with `sides` faces, returning a value in `1..=sides`.
+    fn roll(&mut self, sides: u64) -> u64;
+}
+
+impl<R: RngCore> DiceRng for R {
+    fn roll(&mut self, sides: u64) -> u64 {
+        Uniform::new_inclusive(1, sides).sample(self)
+    }
+}
+
+
+/// Rolls a fixed sequence of values, so that tests can choose exactly what each die shows.
+///
+/// Panics if it runs out of values or is asked for a value that the die can't show.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FixedRolls {
+    values: Vec<u64>,
+    next: usize,
+}
+
+impl FixedRolls {
+    pub fn new(values: impl IntoIterator<Item=u64>) -> Self {
+        Self { values: values.into_iter().collect(), next: 0 }
+    }
+}
+
+impl DiceRng for FixedRolls {
+    fn roll(&mut self, sides: u64) -> u64 {
+        let value = *self.values
+            .get(self.next)
+            .unwrap_or_else(|| panic!("ran out of fixed rolls after {} of them", self.next));
+
+        assert!(
+            (1..=sides).contains(&value),
+            "fixed roll {} is out of range for a d{}", value, sides,
+        );
+
+        self.next += 1;
+
+        value
+    }
+}
+
+
+/// A request for the value of one physical die.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct DieRequest {
+    pub sides: u64,
+    /// Which draw of the roll this is, counting from 1. Rerolls and explosions are new draws.
+    pub position: usize,
+    /// The previous answer for this draw, if it was out of range and is being asked again.
+    pub rejected: Option<u64>,
+}
+
+
+/// Rolls dice by asking for their values, so that a roll can be made with real dice.
+///
+/// `ask` is called once for each die, in the order the expression rolls them, and asked again
+/// until it gives a value the die can show. Rerolls, explosions, and the rest are then applied to
+/// the values given, just as they would be to random ones.
+pub struct ManualRolls<F> {
+    ask: F,
+    position: usize,
+}
+
+impl<F: FnMut(DieRequest) -> u64> ManualRolls<F> {
+    pub fn new(ask: F) -> Self {
+        Self { ask, position: 0 }
+    }
+}
+
+impl<F: FnMut(DieRequest) -> u64> DiceRng for ManualRolls<F> {
+    fn roll(&mut self, sides: u64) -> u64 {
+        self.position += 1;
+
+        let mut request = DieRequest { sides, position: self.position, rejected: None };
+
+        loop {
+            let value = (self.ask)(request);
+
+            if (1..=sides).contains(&value) {
+                return value;
+            }
+
+            request.rejected = Some(value);
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{roll_with, roll_with_seed, RollContext, SEED};
+    use rand::prelude::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    fn parse(input: &str) -> ast::Root {
+        ast::Root::cast(parser::parse(input).syntax()).unwrap()
+    }
+
+    fn check(input: &str, rolls: &[u64], expected_total: i64) {
+        let ctx = RollContext::new(FixedRolls::new(rolls.iter().copied()));
+
+        assert_eq!(roll_with(parse(input), ctx).total(), Ok(expected_total));
+    }
+
+    #[test]
+    fn fixed_rolls_in_order() {
+        let mut rng = FixedRolls::new(vec![3, 1, 6]);
+
+        assert_eq!(rng.roll(6), 3);
+        assert_eq!(rng.roll(6), 1);
+        assert_eq!(rng.roll(6), 6);
+    }
+
+    #[test]
+    #[should_panic(expected = "ran out of fixed rolls after 1 of them")]
+    fn fixed_rolls_run_out() {
+        let mut rng = FixedRolls::new(vec![3]);
+
+        rng.roll(6);
+        rng.roll(6);
+    }
+
+    #[test]
+    #[should_panic(expected = "fixed roll 7 is out of range for a d6")]
+    fn fixed_rolls_out_of_range() {
+        FixedRolls::new(vec![7]).roll(6);
+    }
+
+    #[test]
+    fn manual_rolls_ask_for_each_die() {
+        let requests = Rc::new(RefCell::new(Vec::new()));
+        let mut answers = vec![1, 4, 3].into_iter();
+
+        let asked = Rc::clone(&requests);
+        let rng = ManualRolls::new(move |request| {
+            asked.borrow_mut().push(request);
+            answers.next().unwrap()
+        });
+
+        assert_eq!(roll_with(parse("2d6ro1"), RollContext::new(rng)).total(), Ok(7));
+        assert_eq!(*requests.borrow(), vec![
+            DieRequest { sides: 6, position: 1, rejected: None },
+            DieRequest { sides: 6, position: 2, rejected: None },
+            DieRequest { sides: 6, position: 3, rejected: None },
+        ]);
+    }
+
+    #[test]
+    fn manual_rolls_ask_again_when_out_of_range() {
+        let mut requests = Vec::new();
+        let mut answers = vec![0, 21, 20].into_iter();
+
+        let mut rng = ManualRolls::new(|request| {
+            requests.push(request);
+            answers.next().unwrap()
+        });
+
+        assert_eq!(rng.roll(20), 20);
+        assert_eq!(requests, vec![
+            DieRequest { sides: 20, position: 1, rejected: None },
+            DieRequest { sides: 20, position: 1, rejected: Some(0) },
+            DieRequest { sides: 20, position: 1, rejected: Some(21) },
+        ]);
+    }
+
+    #[test]
+    fn rand_rng_rolls_in_range() {
+        let mut rng = StdRng::seed_from_u64(SEED);
+
+        assert!((0..100).map(|_| rng.roll(4)).all(|value| (1..=4).contains(&value)));
+    }
+
+    #[test]
+    fn roll_with_fixed_rolls() {
+        check("4d6kh3 + 2", &[6, 5, 2, 4], 17);
+    }
+
+    #[test]
+    fn roll_with_fixed_rerolls() {
+        check("2d6ro1", &[1, 4, 3], 7);
+    }
+
+    #[test]
+    fn roll_with_fixed_rolls_in_consecutive_pools() {
+        check("(2d6, 2d6)", &[1, 2, 3, 4], 10);
+    }
+
+    #[test]
+    fn roll_with_seeded_context() {
+        let total = |seed| roll_with(parse("10d20"), RollContext::seeded(seed)).total();
+
+        assert_eq!(total(SEED), total(SEED));
+    }
+
+    #[test]
+    fn seeded_context_rolls_the_same_dice() {
+        let mut ctx = RollContext::seeded(SEED);
+        let rolls: Vec<_> = (0..10).map(|_| ctx.roll(20)).collect();
+
+        // These values are part of the seeded rolling guarantee, so they must never change.
+        assert_eq!(rolls, vec![5, 15, 16, 17, 5, 5, 11, 1, 17, 8]);
+    }
+
+    #[test]
+    fn roll_with_seed_rolls_the_same_total() {
+        let total = roll_with_seed(parse("4d6kh3 + 1d20 - 2d8ro1"), 0).total();
+
+        // This value is part of the seeded rolling guarantee, so it must never change.
+        assert_eq!(total, Ok(11));
+    }
+}
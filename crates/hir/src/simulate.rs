@@ -0,0 +1,237 @@
+use crate::{Program, RollContext, RollError};
+use rand::SeedableRng;
+use rand_chacha::ChaCha12Rng;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::panic;
+use std::thread;
+
+
+/// How many samples are drawn from each random number stream.
+///
+/// The samples are split into chunks of this size, and each chunk draws from its own stream of a
+/// ChaCha12 generator. Workers can then take chunks in any order, and a seeded simulation gives
+/// the same results however many threads it runs on.
+const CHUNK_SIZE: u64 = 4096;
+
+
+/// How many times each total was rolled.
+pub type Histogram = BTreeMap<i64, u64>;
+
+
+/// The results of rolling an expression many times.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Simulation {
+    pub histogram: Histogram,
+    pub samples: u64,
+    /// The mean total, or 0 if nothing was rolled.
+    pub mean: f64,
+    /// The population variance of the totals, or 0 if nothing was rolled.
+    pub variance: f64,
+}
+
+impl Simulation {
+    fn new(histogram: Histogram) -> Self {
+        let samples: u64 = histogram.values().sum();
+
+        if samples == 0 {
+            return Self { histogram, samples, mean: 0.0, variance: 0.0 };
+        }
+
+        let weighted = |f: &dyn Fn(f64) -> f64| {
+            histogram.iter()
+                .map(|(&total, &count)| f(total as f64) * count as f64)
+                .sum::<f64>() / samples as f64
+        };
+
+        let mean = weighted(&|total| total);
+        let variance = weighted(&|total| (total - mean).powi(2));
+
+        Self { histogram, samples, mean, variance }
+    }
+
+    /// The smallest total that at least `p` percent of the rolls were less than or equal to, or
+    /// `None` if nothing was rolled.
+    ///
+    /// Panics if `p` isn't between 0 and 100.
+    pub fn percentile(&self, p: f64) -> Option<i64> {
+        assert!((0.0..=100.0).contains(&p), "percentile {} is not between 0 and 100", p);
+
+        let rank = ((p / 100.0 * self.samples as f64).ceil() as u64).max(1);
+        let mut seen = 0;
+
+        self.histogram.iter().find_map(|(&total, &count)| {
+            seen += count;
+            (seen >= rank).then_some(total)
+        })
+    }
+}
+
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SimulationError {
+    /// One of the rolls had no total, like when it divided by zero.
+    RollFailed(RollError),
+}
+
+impl fmt::Display for SimulationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::RollFailed(error) => write!(f, "rolling the expression failed: {}", error),
+        }
+    }
+}
+
+
+/// Rolls `program` `n` times across all available threads.
+pub fn simulate(program: &Program, n: u64) -> Result<Simulation, SimulationError> {
+    simulate_with_seed(program, n, rand::random())
+}
+
+/// Rolls `program` `n` times across all available threads, with dice determined entirely by
+/// `seed`.
+///
+/// If any roll fails, like by dividing by zero, the simulation stops and returns why.
+pub fn simulate_with_seed(program: &Program, n: u64, seed: u64) -> Result<Simulation, SimulationError> {
+    let chunks = n.div_ceil(CHUNK_SIZE);
+    let workers = thread::available_parallelism().map_or(1, |workers| workers.get() as u64);
+    let workers = workers.min(chunks).max(1);
+
+    let histogram = thread::scope(|scope| {
+        let handles: Vec<_> = (0..workers)
+            .map(|worker| scope.spawn(move || {
+                let mut histogram = Histogram::new();
+
+                for chunk in (worker..chunks).step_by(workers as usize) {
+                    let samples = CHUNK_SIZE.min(n - chunk * CHUNK_SIZE);
+                    sample(program, seed, chunk, samples, &mut histogram)?;
+                }
+
+                Ok(histogram)
+            }))
+            .collect();
+
+        // Every worker is joined before any failure is returned, so none of them outlive the
+        // scope.
+        let histograms: Vec<_> = handles
+            .into_iter()
+            .map(|handle| handle.join().unwrap_or_else(|panic| panic::resume_unwind(panic)))
+            .collect();
+
+        histograms.into_iter().try_fold(Histogram::new(), |mut acc, histogram| {
+            for (total, count) in histogram.map_err(SimulationError::RollFailed)? {
+                *acc.entry(total).or_insert(0) += count;
+            }
+
+            Ok(acc)
+        })
+    })?;
+
+    Ok(Simulation::new(histogram))
+}
+
+fn sample(program: &Program, seed: u64, stream: u64, samples: u64,
+          histogram: &mut Histogram) -> Result<(), RollError> {
+    let mut rng = ChaCha12Rng::seed_from_u64(seed);
+    rng.set_stream(stream);
+
+    let mut ctx = RollContext::new(rng);
+
+    for _ in 0..samples {
+        let result = program.roll(ctx);
+        *histogram.entry(result.total()?).or_insert(0) += 1;
+
+        ctx = result.db.ctx;
+    }
+
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::SEED;
+
+    fn compile(input: &str) -> Program {
+        Program::compile(ast::Root::cast(parser::parse(input).syntax()).unwrap())
+    }
+
+    #[test]
+    fn simulates_every_sample() {
+        let simulation = simulate(&compile("1d6"), 10_000).unwrap();
+
+        let totals: Vec<_> = simulation.histogram.keys().copied().collect();
+
+        assert_eq!(simulation.samples, 10_000);
+        assert_eq!(totals, vec![1, 2, 3, 4, 5, 6]);
+    }
+
+    #[test]
+    fn seeded_simulations_are_deterministic() {
+        let program = compile("4d6kh3");
+
+        assert_eq!(
+            simulate_with_seed(&program, 3 * CHUNK_SIZE + 1, SEED).unwrap(),
+            simulate_with_seed(&program, 3 * CHUNK_SIZE + 1, SEED).unwrap(),
+        );
+    }
+
+    #[test]
+    fn chunks_draw_from_independent_streams() {
+        let simulation = simulate_with_seed(&compile("1d1000000000000"), 2 * CHUNK_SIZE, SEED).unwrap();
+
+        // Chunks that shared a stream would roll the same totals twice.
+        assert!(simulation.histogram.values().all(|&count| count == 1));
+    }
+
+    #[test]
+    fn mean_and_variance_converge() {
+        let simulation = simulate_with_seed(&compile("2d6"), 200_000, SEED).unwrap();
+
+        assert!((simulation.mean - 7.0).abs() < 0.05, "mean was {}", simulation.mean);
+        assert!(
+            (simulation.variance - 35.0 / 6.0).abs() < 0.1,
+            "variance was {}", simulation.variance,
+        );
+    }
+
+    #[test]
+    fn constant_expression() {
+        let simulation = simulate_with_seed(&compile("5 * 3"), 100, SEED).unwrap();
+
+        assert_eq!(simulation.mean, 15.0);
+        assert_eq!(simulation.variance, 0.0);
+        assert_eq!(simulation.percentile(0.0), Some(15));
+        assert_eq!(simulation.percentile(100.0), Some(15));
+    }
+
+    #[test]
+    fn percentiles() {
+        let simulation = Simulation::new(Histogram::from([(1, 1), (2, 2), (3, 6), (10, 1)]));
+
+        assert_eq!(simulation.percentile(0.0), Some(1));
+        assert_eq!(simulation.percentile(10.0), Some(1));
+        assert_eq!(simulation.percentile(11.0), Some(2));
+        assert_eq!(simulation.percentile(50.0), Some(3));
+        assert_eq!(simulation.percentile(90.0), Some(3));
+        assert_eq!(simulation.percentile(95.0), Some(10));
+        assert_eq!(simulation.percentile(100.0), Some(10));
+    }
+
+    #[test]
+    fn empty_simulation() {
+        let simulation = simulate(&compile("1d20"), 0).unwrap();
+
+        assert_eq!(simulation.samples, 0);
+        assert_eq!(simulation.mean, 0.0);
+        assert_eq!(simulation.percentile(50.0), None);
+    }
+
+    #[test]
+    fn failed_rolls_are_returned() {
+        let simulation = simulate_with_seed(&compile("1d6 / (1d2 - 1)"), 1000, SEED);
+
+        assert_eq!(simulation, Err(SimulationError::RollFailed(RollError::DivisionByZero)));
+    }
+}
@@ -0,0 +1,160 @@
+use crate::{simulate_with_seed, Bound, Bounds, Comparison, Distribution, Program, SimulationError};
+
+
+/// How many times an expression is rolled to estimate its statistics when they can't be worked
+/// out exactly. The rolls are seeded, so the estimates don't change from one call to the next.
+const SIMULATED_SAMPLES: u64 = 100_000;
+
+
+/// Summary statistics of an expression's total, like "avg 10.5, range 3–18" for `3d6`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Stats {
+    /// The lowest total the expression can roll, from `Bounds`, so it is the same whether or not
+    /// the other statistics are exact.
+    pub min: Bound,
+    /// The highest total the expression can roll, from `Bounds`.
+    pub max: Bound,
+    pub mean: f64,
+    pub std_dev: f64,
+    pub median: i64,
+    /// Whether the statistics are exact. Otherwise they are estimated by rolling the expression
+    /// many times.
+    pub exact: bool,
+    distribution: Distribution,
+}
+
+impl Stats {
+    /// Works out the statistics of `program` exactly if `Distribution::exact` can, and
+    /// estimates them by simulation otherwise. Fails if a simulated roll does, like by dividing
+    /// by zero.
+    pub fn of(program: &Program) -> Result<Self, SimulationError> {
+        let bounds = Bounds::of(program);
+
+        if let Some(distribution) = Distribution::exact(program) {
+            return Ok(Self::new(distribution, bounds, true));
+        }
+
+        let simulation = simulate_with_seed(program, SIMULATED_SAMPLES, 0)?;
+        let distribution = Distribution::from_histogram(&simulation.histogram)
+            .expect("a simulation with samples rolls at least one total");
+
+        Ok(Self::new(distribution, bounds, false))
+    }
+
+    fn new(distribution: Distribution, bounds: Bounds, exact: bool) -> Self {
+        Self {
+            min: bounds.min,
+            max: bounds.max,
+            mean: distribution.mean(),
+            std_dev: distribution.variance().sqrt(),
+            median: distribution.quantile(0.5),
+            exact,
+            distribution,
+        }
+    }
+
+    /// The smallest total that is rolled at or below with probability at least `q`.
+    ///
+    /// Panics if `q` isn't between 0 and 1.
+    pub fn quantile(&self, q: f64) -> i64 {
+        self.distribution.quantile(q)
+    }
+
+    /// The distribution the statistics were worked out from.
+    pub fn distribution(&self) -> &Distribution {
+        &self.distribution
+    }
+}
+
+
+/// How likely `lhs` is to roll higher than, the same as, or lower than `rhs`, alongside whether
+/// the probabilities are exact rather than estimated. See `Stats::of`.
+pub fn compare(lhs: &Program, rhs: &Program) -> Result<(Comparison, bool), SimulationError> {
+    let (lhs, rhs) = (Stats::of(lhs)?, Stats::of(rhs)?);
+
+    Ok((lhs.distribution.compare(&rhs.distribution), lhs.exact && rhs.exact))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn compile(input: &str) -> Program {
+        Program::compile(ast::Root::cast(parser::parse(input).syntax()).unwrap())
+    }
+
+    fn stats(input: &str) -> Stats {
+        Stats::of(&compile(input)).unwrap()
+    }
+
+    #[test]
+    fn exact_stats() {
+        let stats = stats("3d6");
+
+        assert!(stats.exact);
+        assert_eq!((stats.min, stats.max), (Bound::Finite(3), Bound::Finite(18)));
+        assert!((stats.mean - 10.5).abs() < 1e-9);
+        assert!((stats.std_dev - 8.75f64.sqrt()).abs() < 1e-9);
+        assert_eq!(stats.median, 10);
+        assert_eq!(stats.quantile(0.0), 3);
+        assert_eq!(stats.quantile(0.95), 15);
+        assert_eq!(stats.quantile(1.0), 18);
+    }
+
+    #[test]
+    fn literal_stats() {
+        let stats = stats("7");
+
+        assert!(stats.exact);
+        assert_eq!((stats.min, stats.max), (Bound::Finite(7), Bound::Finite(7)));
+        assert_eq!(stats.median, 7);
+        assert_eq!(stats.std_dev, 0.0);
+    }
+
+    #[test]
+    fn compare_programs() {
+        let (comparison, exact) = compare(&compile("1d20 + 5"), &compile("15")).unwrap();
+
+        assert!(exact);
+        assert!((comparison.greater - 0.5).abs() < 1e-9);
+        assert!((comparison.equal - 0.05).abs() < 1e-9);
+        assert!((comparison.less - 0.45).abs() < 1e-9);
+    }
+
+    #[test]
+    fn simulated_stats() {
+        let program = compile("4d6kh3e6");
+        let stats = Stats::of(&program).unwrap();
+        let other = simulate_with_seed(&program, 20_000, 1).unwrap();
+
+        assert!(!stats.exact);
+        assert_eq!((stats.min, stats.max), (Bound::Finite(3), Bound::Infinity));
+        assert!((stats.mean - other.mean).abs() < 0.2, "{} and {}", stats.mean, other.mean);
+        assert_eq!(stats, Stats::of(&program).unwrap());
+    }
+
+    #[test]
+    fn simulated_stats_with_many_totals() {
+        let stats = stats("1d1000000");
+
+        assert!(!stats.exact);
+        assert_eq!((stats.min, stats.max), (Bound::Finite(1), Bound::Finite(1_000_000)));
+        assert!((stats.mean - 500_000.5).abs() < 5000.0, "mean was {}", stats.mean);
+    }
+
+    #[test]
+    fn failed_simulations_are_errors() {
+        let stats = Stats::of(&compile("1d6 / (1d2 - 1)"));
+
+        assert!(matches!(stats, Err(SimulationError::RollFailed(_))), "{:?}", stats);
+    }
+
+    #[test]
+    fn compare_wide_and_narrow_programs() {
+        let (comparison, exact) = compare(&compile("1d1000000"), &compile("1d6")).unwrap();
+
+        assert!(!exact);
+        assert!(comparison.greater > 0.999, "{:?}", comparison);
+    }
+}
@@ -37,6 +37,9 @@ pub enum TokenKind {
     #[token(",")]
     Comma,
 
+    #[token("vs")]
+    Versus,
+
     #[token("k")]
     Keep,
 
@@ -61,6 +64,12 @@ pub enum TokenKind {
     #[token("ma")]
     Max,
 
+    #[token("cs")]
+    CritSuccess,
+
+    #[token("cf")]
+    CritFail,
+
     #[token("h")]
     Highest,
 
@@ -73,13 +82,37 @@ pub enum TokenKind {
     #[token("<")]
     Less,
 
+    #[token(">=")]
+    GreaterEq,
+
+    #[token("<=")]
+    LessEq,
+
+    #[token("->")]
+    Arrow,
+
+    #[token("{")]
+    LBrace,
+
+    #[token("}")]
+    RBrace,
+
+    #[token("..")]
+    DotDot,
+
+    #[regex(":[ ]*[A-Za-z_][A-Za-z0-9_]*")]
+    Label,
+
+    #[regex(r"\[[A-Za-z_][A-Za-z0-9_]*\]")]
+    Tag,
+
     #[error]
     Error,
 }
 
 
 impl TokenKind {
-    pub const SET_OPERATORS: &'static [Self; 8] = &[
+    pub const SET_OPERATORS: &'static [Self; 10] = &[
         Self::Keep,
         Self::Drop,
         Self::Reroll,
@@ -88,6 +121,8 @@ impl TokenKind {
         Self::Explode,
         Self::Min,
         Self::Max,
+        Self::CritSuccess,
+        Self::CritFail,
     ];
 
     pub const SET_SELECTORS: &'static [Self; 5] = &[
@@ -117,6 +152,7 @@ impl fmt::Display for TokenKind {
             Self::LParen => "'('",
             Self::RParen => "')'",
             Self::Comma => "','",
+            Self::Versus => "'vs'",
             Self::Keep => "'k'",
             Self::Drop => "'p'",
             Self::Reroll => "'rr'",
@@ -125,10 +161,20 @@ impl fmt::Display for TokenKind {
             Self::Explode => "'e'",
             Self::Min => "'mi'",
             Self::Max => "'ma'",
+            Self::CritSuccess => "'cs'",
+            Self::CritFail => "'cf'",
             Self::Highest => "'h'",
             Self::Lowest => "'l'",
             Self::Greater => "'>'",
             Self::Less => "'<'",
+            Self::GreaterEq => "'>='",
+            Self::LessEq => "'<='",
+            Self::Arrow => "'->'",
+            Self::LBrace => "'{'",
+            Self::RBrace => "'}'",
+            Self::DotDot => "'..'",
+            Self::Label => "label",
+            Self::Tag => "tag",
             Self::Error => "an unrecognized token",
         })
     }
@@ -208,6 +254,11 @@ mod tests {
         check(",", TokenKind::Comma);
     }
 
+    #[test]
+    fn lex_versus() {
+        check("vs", TokenKind::Versus);
+    }
+
     #[test]
     fn lex_keep() {
         check("k", TokenKind::Keep);
@@ -248,6 +299,16 @@ mod tests {
         check("ma", TokenKind::Max);
     }
 
+    #[test]
+    fn lex_crit_success() {
+        check("cs", TokenKind::CritSuccess);
+    }
+
+    #[test]
+    fn lex_crit_fail() {
+        check("cf", TokenKind::CritFail);
+    }
+
     #[test]
     fn lex_highest() {
         check("h", TokenKind::Highest);
@@ -267,4 +328,44 @@ mod tests {
     fn lex_less() {
         check("<", TokenKind::Less);
     }
+
+    #[test]
+    fn lex_greater_eq() {
+        check(">=", TokenKind::GreaterEq);
+    }
+
+    #[test]
+    fn lex_less_eq() {
+        check("<=", TokenKind::LessEq);
+    }
+
+    #[test]
+    fn lex_arrow() {
+        check("->", TokenKind::Arrow);
+    }
+
+    #[test]
+    fn lex_left_brace() {
+        check("{", TokenKind::LBrace);
+    }
+
+    #[test]
+    fn lex_right_brace() {
+        check("}", TokenKind::RBrace);
+    }
+
+    #[test]
+    fn lex_dot_dot() {
+        check("..", TokenKind::DotDot);
+    }
+
+    #[test]
+    fn lex_label() {
+        check(": partial", TokenKind::Label);
+    }
+
+    #[test]
+    fn lex_tag() {
+        check("[fire]", TokenKind::Tag);
+    }
 }
\ No newline at end of file
@@ -10,7 +10,13 @@ pub(crate) fn root(p: &mut Parser) -> CompletedMarker {
     let m = p.start();
 
     while !p.at_end() {
-        expr::expr(p);
+        let at_recovery = p.at_recovery();
+
+        // Nothing encloses the top level to recover at a token like a stray `)`, so once it has
+        // been reported it is skipped.
+        if expr::expr(p).is_none() && at_recovery {
+            p.bump_error();
+        }
     }
 
     m.complete(p, SyntaxKind::Root)
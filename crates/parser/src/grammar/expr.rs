@@ -7,13 +7,15 @@ enum BinaryOp {
     Sub,
     Mul,
     Div,
+    Versus,
 }
 
 impl BinaryOp {
     fn binding_power(&self) -> (u8, u8) {
         match self {
-            Self::Add | Self::Sub => (1, 2),
-            Self::Mul | Self::Div => (3, 4),
+            Self::Versus => (1, 2),
+            Self::Add | Self::Sub => (3, 4),
+            Self::Mul | Self::Div => (5, 6),
         }
     }
 }
@@ -25,14 +27,20 @@ enum UnaryOp {
 impl UnaryOp {
     fn binding_power(&self) -> ((), u8) {
         match self {
-            Self::Neg => ((), 5),
+            Self::Neg => ((), 7),
         }
     }
 }
 
 
 pub(super) fn expr(p: &mut Parser) -> Option<CompletedMarker> {
-    expr_binding_power(p, 0)
+    let expr = expr_binding_power(p, 0)?;
+
+    if p.at(TokenKind::Arrow) {
+        Some(band_expr(p, expr))
+    } else {
+        Some(expr)
+    }
 }
 
 fn expr_binding_power(p: &mut Parser, minimum_binding_power: u8) -> Option<CompletedMarker> {
@@ -47,6 +55,8 @@ fn expr_binding_power(p: &mut Parser, minimum_binding_power: u8) -> Option<Compl
             BinaryOp::Mul
         } else if p.at(TokenKind::Slash) {
             BinaryOp::Div
+        } else if p.at(TokenKind::Versus) {
+            BinaryOp::Versus
         } else {
             break;
         };
@@ -87,7 +97,11 @@ fn lhs(p: &mut Parser) -> Option<CompletedMarker> {
         return None;
     };
 
-    Some(cm)
+    if p.at(TokenKind::Tag) {
+        Some(tagged_expr(p, cm))
+    } else {
+        Some(cm)
+    }
 }
 
 
@@ -118,15 +132,75 @@ fn set_op(p: &mut Parser) -> CompletedMarker {
     let m = p.start();
     p.bump();
 
-    assert!(p.at_any(TokenKind::SET_SELECTORS));
-    if !p.at(TokenKind::Number) {
+    if p.at_any(&[TokenKind::Highest, TokenKind::Lowest, TokenKind::Greater, TokenKind::Less]) {
         p.bump();
     }
-    literal(p);
+
+    if p.at(TokenKind::Number) {
+        literal(p);
+    } else {
+        p.error();
+    }
 
     m.complete(p, SyntaxKind::SetOp)
 }
 
+fn band_expr(p: &mut Parser, banded: CompletedMarker) -> CompletedMarker {
+    assert!(p.at(TokenKind::Arrow));
+
+    let m = banded.precede(p);
+    p.bump();
+    p.expect(TokenKind::LBrace);
+
+    while !p.at(TokenKind::RBrace) && !p.at_end() {
+        band(p);
+
+        if p.at(TokenKind::Comma) {
+            p.bump();
+        } else {
+            break;
+        }
+    }
+
+    p.expect(TokenKind::RBrace);
+    m.complete(p, SyntaxKind::BandExpr)
+}
+
+fn band(p: &mut Parser) -> CompletedMarker {
+    let m = p.start();
+
+    if p.at_any(&[TokenKind::LessEq, TokenKind::GreaterEq, TokenKind::Less, TokenKind::Greater]) {
+        p.bump();
+        band_bound(p);
+    } else {
+        band_bound(p);
+
+        if p.at(TokenKind::DotDot) {
+            p.bump();
+            band_bound(p);
+        }
+    }
+
+    p.expect(TokenKind::Label);
+    m.complete(p, SyntaxKind::Band)
+}
+
+fn band_bound(p: &mut Parser) {
+    if p.at(TokenKind::Number) {
+        literal(p);
+    } else {
+        p.error();
+    }
+}
+
+fn tagged_expr(p: &mut Parser, tagged: CompletedMarker) -> CompletedMarker {
+    assert!(p.at(TokenKind::Tag));
+
+    let m = tagged.precede(p);
+    p.bump();
+    m.complete(p, SyntaxKind::TaggedExpr)
+}
+
 fn prefix_expr(p: &mut Parser) -> CompletedMarker {
     assert!(p.at(TokenKind::Minus));
 
@@ -152,7 +226,7 @@ fn paren_or_set_expr(p: &mut Parser) -> CompletedMarker {
     if p.at(TokenKind::RParen) {
         set_expr(p, m)
     } else {
-        expr_binding_power(p, 0);
+        item(p);
 
         if p.at(TokenKind::Comma) {
             set_expr(p, m)
@@ -169,7 +243,7 @@ fn set_expr(p: &mut Parser, m: Marker) -> CompletedMarker {
         if p.at(TokenKind::RParen) {
             break;
         } else {
-            expr_binding_power(p, 0);
+            item(p);
         }
     }
 
@@ -182,6 +256,17 @@ fn set_expr(p: &mut Parser, m: Marker) -> CompletedMarker {
     m.complete(p, SyntaxKind::SetExpr)
 }
 
+/// Parses an item between parentheses. An operator can't start an item, so one found instead is
+/// skipped once it has been reported, and the rest of the items are still parsed.
+fn item(p: &mut Parser) {
+    if expr_binding_power(p, 0).is_none()
+        && p.at_recovery()
+        && !p.at_any(&[TokenKind::Comma, TokenKind::RParen])
+    {
+        p.bump_error();
+    }
+}
+
 
 #[cfg(test)]
 mod tests {
@@ -261,6 +346,31 @@ Root@0..7
         );
     }
 
+    #[test]
+    fn versus_has_lower_binding_power_than_arithmetic() {
+        check(
+            "1d20+5 vs 1d20+3",
+            expect![[r#"
+Root@0..16
+  InfixExpr@0..16
+    InfixExpr@0..7
+      DiceExpr@0..4
+        Dice@0..4 "1d20"
+      Plus@4..5 "+"
+      Literal@5..7
+        Number@5..6 "5"
+        Whitespace@6..7 " "
+    Versus@7..9 "vs"
+    Whitespace@9..10 " "
+    InfixExpr@10..16
+      DiceExpr@10..14
+        Dice@10..14 "1d20"
+      Plus@14..15 "+"
+      Literal@15..16
+        Number@15..16 "3""#]],
+        );
+    }
+
     #[test]
     fn parse_negation() {
         check(
@@ -603,6 +713,26 @@ Root@0..9
         );
     }
 
+    #[test]
+    fn parse_dice_with_crit_ranges() {
+        check(
+            "1d20cs>19cf1",
+            expect![[r#"
+Root@0..12
+  DiceExpr@0..12
+    Dice@0..4 "1d20"
+    SetOp@4..9
+      CritSuccess@4..6 "cs"
+      Greater@6..7 ">"
+      Literal@7..9
+        Number@7..9 "19"
+    SetOp@9..12
+      CritFail@9..11 "cf"
+      Literal@11..12
+        Number@11..12 "1""#]],
+        );
+    }
+
     #[test]
     fn parse_set_with_single_set_operation() {
         check(
@@ -678,7 +808,127 @@ Root@0..4
     LParen@0..1 "("
     DiceExpr@1..4
       Dice@1..4 "1d4"
-error at 1..4: expected 'k', 'p', 'rr', 'ro', 'ra', 'e', 'mi', 'ma', '+', '-', '*', '/', ',', or ')'"#]],
+error at 1..4: expected 'k', 'p', 'rr', 'ro', 'ra', 'e', 'mi', 'ma', 'cs', 'cf', tag, '+', '-', '*', '/', 'vs', ',', or ')'"#]],
+        );
+    }
+
+    #[test]
+    fn parse_tagged_dice() {
+        check(
+            "2d6[fire]",
+            expect![[r#"
+Root@0..9
+  TaggedExpr@0..9
+    DiceExpr@0..3
+      Dice@0..3 "2d6"
+    Tag@3..9 "[fire]""#]],
+        );
+    }
+
+    #[test]
+    fn parse_tagged_operands() {
+        check(
+            "2d6[fire] + 3[fire]",
+            expect![[r#"
+Root@0..19
+  InfixExpr@0..19
+    TaggedExpr@0..10
+      DiceExpr@0..3
+        Dice@0..3 "2d6"
+      Tag@3..9 "[fire]"
+      Whitespace@9..10 " "
+    Plus@10..11 "+"
+    Whitespace@11..12 " "
+    TaggedExpr@12..19
+      Literal@12..13
+        Number@12..13 "3"
+      Tag@13..19 "[fire]""#]],
+        );
+    }
+
+    #[test]
+    fn parse_band_expr() {
+        check(
+            "2d6+2 -> {<=6: miss, 7..9: partial, >=10: hit}",
+            expect![[r#"
+Root@0..46
+  BandExpr@0..46
+    InfixExpr@0..6
+      DiceExpr@0..3
+        Dice@0..3 "2d6"
+      Plus@3..4 "+"
+      Literal@4..6
+        Number@4..5 "2"
+        Whitespace@5..6 " "
+    Arrow@6..8 "->"
+    Whitespace@8..9 " "
+    LBrace@9..10 "{"
+    Band@10..19
+      LessEq@10..12 "<="
+      Literal@12..13
+        Number@12..13 "6"
+      Label@13..19 ": miss"
+    Comma@19..20 ","
+    Whitespace@20..21 " "
+    Band@21..34
+      Literal@21..22
+        Number@21..22 "7"
+      DotDot@22..24 ".."
+      Literal@24..25
+        Number@24..25 "9"
+      Label@25..34 ": partial"
+    Comma@34..35 ","
+    Whitespace@35..36 " "
+    Band@36..45
+      GreaterEq@36..38 ">="
+      Literal@38..40
+        Number@38..40 "10"
+      Label@40..45 ": hit"
+    RBrace@45..46 "}""#]],
+        );
+    }
+
+    #[test]
+    fn parse_band_with_trailing_comma() {
+        check(
+            "1d20 -> {20: crit,}",
+            expect![[r#"
+Root@0..19
+  BandExpr@0..19
+    DiceExpr@0..5
+      Dice@0..4 "1d20"
+      Whitespace@4..5 " "
+    Arrow@5..7 "->"
+    Whitespace@7..8 " "
+    LBrace@8..9 "{"
+    Band@9..17
+      Literal@9..11
+        Number@9..11 "20"
+      Label@11..17 ": crit"
+    Comma@17..18 ","
+    RBrace@18..19 "}""#]],
+        );
+    }
+
+    #[test]
+    fn parse_band_without_label() {
+        check(
+            "1d20 -> {>10}",
+            expect![[r#"
+Root@0..13
+  BandExpr@0..13
+    DiceExpr@0..5
+      Dice@0..4 "1d20"
+      Whitespace@4..5 " "
+    Arrow@5..7 "->"
+    Whitespace@7..8 " "
+    LBrace@8..9 "{"
+    Band@9..12
+      Greater@9..10 ">"
+      Literal@10..12
+        Number@10..12 "10"
+    RBrace@12..13 "}"
+error at 12..13: expected label, but found '}'"#]],
         );
     }
 
@@ -705,8 +955,151 @@ Root@0..3
       Literal@1..2
         Number@1..2 "1"
       Plus@2..3 "+"
-error at 2..3: expected number, dice, '-', or '('
-error at 2..3: expected ',' or ')'"#]],
+error at 2..3: expected number, dice, '-', '(', ',', or ')'"#]],
+        );
+    }
+
+    #[test]
+    fn parse_set_op_without_number() {
+        check(
+            "4d6k",
+            expect![[r#"
+Root@0..4
+  DiceExpr@0..4
+    Dice@0..3 "4d6"
+    SetOp@3..4
+      Keep@3..4 "k"
+error at 3..4: expected 'h', 'l', '>', '<', or number"#]],
+        );
+    }
+
+    #[test]
+    fn recover_from_set_op_without_selector_or_number() {
+        check(
+            "4d6k+1",
+            expect![[r#"
+Root@0..6
+  InfixExpr@0..6
+    DiceExpr@0..4
+      Dice@0..3 "4d6"
+      SetOp@3..4
+        Keep@3..4 "k"
+    Plus@4..5 "+"
+    Literal@5..6
+      Number@5..6 "1"
+error at 4..5: expected 'h', 'l', '>', '<', or number, but found '+'"#]],
+        );
+    }
+
+    #[test]
+    fn recover_from_set_op_selector_without_number() {
+        check(
+            "(2d20kh, 3)kl1",
+            expect![[r#"
+Root@0..14
+  SetExpr@0..14
+    LParen@0..1 "("
+    DiceExpr@1..7
+      Dice@1..5 "2d20"
+      SetOp@5..7
+        Keep@5..6 "k"
+        Highest@6..7 "h"
+    Comma@7..8 ","
+    Whitespace@8..9 " "
+    Literal@9..10
+      Number@9..10 "3"
+    RParen@10..11 ")"
+    SetOp@11..14
+      Keep@11..12 "k"
+      Lowest@12..13 "l"
+      Literal@13..14
+        Number@13..14 "1"
+error at 7..8: expected number, but found ','"#]],
+        );
+    }
+
+    #[test]
+    fn skip_unknown_token_in_set_op() {
+        check(
+            "1d6rx",
+            expect![[r#"
+Root@0..5
+  DiceExpr@0..3
+    Dice@0..3 "1d6"
+  Error@3..4
+    Error@3..4 "r"
+  Error@4..5
+    Error@4..5 "x"
+error at 3..4: expected 'k', 'p', 'rr', 'ro', 'ra', 'e', 'mi', 'ma', 'cs', 'cf', tag, '+', '-', '*', '/', 'vs', '->', number, dice, or '(', but found an unrecognized token
+error at 4..5: expected number, dice, '-', or '(', but found an unrecognized token"#]],
+        );
+    }
+
+    #[test]
+    fn recover_from_stray_closing_paren() {
+        check(
+            "1d4) + 2",
+            expect![[r#"
+Root@0..8
+  DiceExpr@0..3
+    Dice@0..3 "1d4"
+  Error@3..5
+    RParen@3..4 ")"
+    Whitespace@4..5 " "
+  Error@5..7
+    Plus@5..6 "+"
+    Whitespace@6..7 " "
+  Literal@7..8
+    Number@7..8 "2"
+error at 3..4: expected 'k', 'p', 'rr', 'ro', 'ra', 'e', 'mi', 'ma', 'cs', 'cf', tag, '+', '-', '*', '/', 'vs', '->', number, dice, or '(', but found ')'
+error at 5..6: expected number, dice, '-', or '(', but found '+'"#]],
+        );
+    }
+
+    #[test]
+    fn recover_from_missing_operand() {
+        check(
+            "(1 + )",
+            expect![[r#"
+Root@0..6
+  ParenExpr@0..6
+    LParen@0..1 "("
+    InfixExpr@1..5
+      Literal@1..3
+        Number@1..2 "1"
+        Whitespace@2..3 " "
+      Plus@3..4 "+"
+      Whitespace@4..5 " "
+    RParen@5..6 ")"
+error at 5..6: expected number, dice, '-', or '(', but found ')'"#]],
+        );
+    }
+
+    #[test]
+    fn skip_operator_in_place_of_set_item() {
+        check(
+            "(1, +, 2)kh1",
+            expect![[r#"
+Root@0..12
+  SetExpr@0..12
+    LParen@0..1 "("
+    Literal@1..2
+      Number@1..2 "1"
+    Comma@2..3 ","
+    Whitespace@3..4 " "
+    Error@4..5
+      Plus@4..5 "+"
+    Comma@5..6 ","
+    Whitespace@6..7 " "
+    Literal@7..8
+      Number@7..8 "2"
+    RParen@8..9 ")"
+    SetOp@9..12
+      Keep@9..10 "k"
+      Highest@10..11 "h"
+      Literal@11..12
+        Number@11..12 "1"
+error at 4..5: expected ')', number, dice, '-', or '(', but found '+'"#]],
         );
     }
-}
\ No newline at end of file
+}
@@ -1,3 +1,4 @@
+use diagnostic::Diagnostic;
 use rowan::GreenNode;
 
 use lexer::Lexer;
@@ -45,6 +46,11 @@ impl Parse {
         s
     }
 
+    /// Everything that went wrong while parsing, in the order it was found.
+    pub fn diagnostics(&self) -> Vec<Diagnostic> {
+        self.errors.iter().map(ParseError::diagnostic).collect()
+    }
+
     pub fn syntax(&self) -> SyntaxNode {
         SyntaxNode::new_root(self.green_node.clone())
     }
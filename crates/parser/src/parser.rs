@@ -12,7 +12,19 @@ use std::mem;
 use syntax::SyntaxKind;
 
 
-const RECOVERY_SET: [TokenKind; 0] = [];
+/// Tokens that an enclosing rule can carry on from, so an error at one of them doesn't skip it:
+/// in `4d6k+1`, the `+` is missing its selector's number but still adds 1.
+const RECOVERY_SET: [TokenKind; 9] = [
+    TokenKind::Plus,
+    TokenKind::Minus,
+    TokenKind::Star,
+    TokenKind::Slash,
+    TokenKind::Versus,
+    TokenKind::Comma,
+    TokenKind::RParen,
+    TokenKind::Arrow,
+    TokenKind::RBrace,
+];
 
 
 pub(crate) struct Parser<'t, 'input> {
@@ -59,7 +71,7 @@ impl<'t, 'input> Parser<'t, 'input> {
 
     pub(crate) fn at_any(&mut self, options: &[TokenKind]) -> bool {
         self.expected_kinds.append(&mut options.to_vec());
-        self.peek().map_or(false, |k| options.contains(&k))
+        self.peek().is_some_and(|k| options.contains(&k))
     }
 
     pub(crate) fn expect(&mut self, kind: TokenKind) {
@@ -74,10 +86,12 @@ impl<'t, 'input> Parser<'t, 'input> {
         self.peek().is_none()
     }
 
-    fn at_recovery(&mut self) -> bool {
-        self.peek().map_or(false, |k| RECOVERY_SET.contains(&k))
+    pub(crate) fn at_recovery(&mut self) -> bool {
+        self.peek().is_some_and(|k| RECOVERY_SET.contains(&k))
     }
 
+    /// Reports that the current token isn't one of the expected kinds, and skips it unless it is
+    /// in the recovery set.
     pub(crate) fn error(&mut self) {
         let current_token = self.source.peek_token();
 
@@ -88,18 +102,50 @@ impl<'t, 'input> Parser<'t, 'input> {
             (None, self.source.last_token_range().unwrap())
         };
 
-        self.events.push(Event::Error(ParseError {
-            expected: mem::take(&mut self.expected_kinds),
-            found,
-            range
-        }));
+        let mut expected = mem::take(&mut self.expected_kinds);
+        dedup_kinds(&mut expected);
+
+        // A token that isn't skipped can be found unexpected by several rules in a row, like the
+        // `)` of `(1 + )`; those are reported as one error.
+        let earlier_error = self.events
+            .iter_mut()
+            .rev()
+            .take_while(|event| **event != Event::AddToken)
+            .find_map(|event| match event {
+                Event::Error(error) if error.range == range => Some(error),
+                _ => None,
+            });
+
+        match earlier_error {
+            Some(error) => {
+                error.expected.append(&mut expected);
+                dedup_kinds(&mut error.expected);
+            }
+            None => self.events.push(Event::Error(ParseError { expected, found, range })),
+        }
 
         if !self.at_recovery() && !self.at_end() {
-            let m = self.start();
-            self.bump();
-            m.complete(self, SyntaxKind::Error);
+            self.bump_error();
         }
     }
+
+    /// Skips the current token, wrapping it in an error node.
+    pub(crate) fn bump_error(&mut self) {
+        let m = self.start();
+        self.bump();
+        m.complete(self, SyntaxKind::Error);
+    }
+}
+
+
+/// Removes repeated kinds, keeping the first of each.
+fn dedup_kinds(kinds: &mut Vec<TokenKind>) {
+    let mut seen = Vec::new();
+    kinds.retain(|kind| {
+        let first = !seen.contains(kind);
+        seen.push(*kind);
+        first
+    });
 }
 
 
@@ -1,3 +1,4 @@
+use diagnostic::{Diagnostic, Label};
 use std::fmt;
 use lexer::TokenKind;
 use text_size::TextRange;
@@ -10,35 +11,62 @@ pub(crate) struct ParseError {
     pub(super) range: TextRange,
 }
 
-impl fmt::Display for ParseError {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f,
-            "error at {}..{}: expected ",
-            u32::from(self.range.start()),
-            u32::from(self.range.end()),
-        )?;
+impl ParseError {
+    /// The error as a diagnostic. Errors where a token was found have the code `E0001`, and
+    /// errors at the end of the input have the code `E0002`.
+    pub(crate) fn diagnostic(&self) -> Diagnostic {
+        let (code, label) = match self.found {
+            Some(found) => ("E0001", format!("found {}", found)),
+            None => ("E0002", "the input ends here".to_string()),
+        };
+
+        let diagnostic = Diagnostic::error(code, self.message(), Label::new(self.range, label));
+
+        if self.expected.contains(&TokenKind::RParen) {
+            diagnostic.with_help("add a ')' to close the parentheses")
+        } else if self.expected.contains(&TokenKind::RBrace) {
+            diagnostic.with_help("add a '}' to close the bands")
+        } else if self.expected.contains(&TokenKind::Label) {
+            diagnostic.with_help("give each band a label, like `>=10: hit`")
+        } else {
+            diagnostic
+        }
+    }
 
+    fn message(&self) -> String {
+        let mut message = "expected ".to_string();
         let num_expected = self.expected.len();
 
         for (idx, expected_kind) in self.expected.iter().enumerate() {
             if idx == 0 {
-                write!(f, "{}", expected_kind)?;
+                message.push_str(&expected_kind.to_string());
             } else if idx == num_expected - 1 {
                 if num_expected == 2 {
-                    write!(f, " or {}", expected_kind)?;
+                    message.push_str(&format!(" or {}", expected_kind));
                 } else {
-                    write!(f, ", or {}", expected_kind)?;
+                    message.push_str(&format!(", or {}", expected_kind));
                 }
             } else {
-                write!(f, ", {}", expected_kind)?;
+                message.push_str(&format!(", {}", expected_kind));
             }
         }
 
         if let Some(found) = self.found {
-            write!(f, ", but found {}", found)?;
+            message.push_str(&format!(", but found {}", found));
         }
 
-        Ok(())
+        message
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f,
+            "error at {}..{}: {}",
+            u32::from(self.range.start()),
+            u32::from(self.range.end()),
+            self.message(),
+        )
     }
 }
 
@@ -108,4 +136,35 @@ mod tests {
             "error at 0..1: expected '+' or '-', but found '>'",
         );
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn diagnostic() {
+        let error = ParseError {
+            expected: vec![TokenKind::Comma, TokenKind::RParen],
+            found: None,
+            range: TextRange::new(1.into(), 4.into()),
+        };
+
+        assert_eq!(error.diagnostic().render("(1d4"), "\
+error[E0002]: expected ',' or ')'
+  |
+1 | (1d4
+  |  ^^^ the input ends here
+  = help: add a ')' to close the parentheses");
+    }
+
+    #[test]
+    fn diagnostic_for_an_unrecognized_token() {
+        let error = ParseError {
+            expected: vec![TokenKind::Number],
+            found: Some(TokenKind::Error),
+            range: TextRange::new(4.into(), 5.into()),
+        };
+
+        assert_eq!(error.diagnostic().render("1d6rx"), "\
+error[E0001]: expected number, but found an unrecognized token
+  |
+1 | 1d6rx
+  |     ^ found an unrecognized token");
+    }
+}
@@ -16,6 +16,7 @@ pub enum SyntaxKind {
     LParen,
     RParen,
     Comma,
+    Versus,
     Keep,
     Drop,
     Reroll,
@@ -24,13 +25,25 @@ pub enum SyntaxKind {
     Explode,
     Min,
     Max,
+    CritSuccess,
+    CritFail,
     Highest,
     Lowest,
     Greater,
     Less,
+    GreaterEq,
+    LessEq,
+    Arrow,
+    LBrace,
+    RBrace,
+    DotDot,
+    Label,
+    Tag,
     Error,
 
     Root,
+    Band,
+    BandExpr,
     DiceExpr,
     InfixExpr,
     Literal,
@@ -38,10 +51,11 @@ pub enum SyntaxKind {
     PrefixExpr,
     SetExpr,
     SetOp,
+    TaggedExpr,
 }
 
 impl SyntaxKind {
-    pub const SET_OPERATORS: &'static [Self; 8] = &[
+    pub const SET_OPERATORS: &'static [Self; 10] = &[
         Self::Keep,
         Self::Drop,
         Self::Reroll,
@@ -50,6 +64,8 @@ impl SyntaxKind {
         Self::Explode,
         Self::Min,
         Self::Max,
+        Self::CritSuccess,
+        Self::CritFail,
     ];
 
     pub const SET_SELECTORS: &'static [Self; 5] = &[
@@ -75,6 +91,7 @@ impl From<TokenKind> for SyntaxKind {
             TokenKind::LParen => Self::LParen,
             TokenKind::RParen => Self::RParen,
             TokenKind::Comma => Self::Comma,
+            TokenKind::Versus => Self::Versus,
             TokenKind::Keep => Self::Keep,
             TokenKind::Drop => Self::Drop,
             TokenKind::Reroll => Self::Reroll,
@@ -83,10 +100,20 @@ impl From<TokenKind> for SyntaxKind {
             TokenKind::Explode => Self::Explode,
             TokenKind::Min => Self::Min,
             TokenKind::Max => Self::Max,
+            TokenKind::CritSuccess => Self::CritSuccess,
+            TokenKind::CritFail => Self::CritFail,
             TokenKind::Highest => Self::Highest,
             TokenKind::Lowest => Self::Lowest,
             TokenKind::Greater => Self::Greater,
             TokenKind::Less => Self::Less,
+            TokenKind::GreaterEq => Self::GreaterEq,
+            TokenKind::LessEq => Self::LessEq,
+            TokenKind::Arrow => Self::Arrow,
+            TokenKind::LBrace => Self::LBrace,
+            TokenKind::RBrace => Self::RBrace,
+            TokenKind::DotDot => Self::DotDot,
+            TokenKind::Label => Self::Label,
+            TokenKind::Tag => Self::Tag,
             TokenKind::Error => Self::Error,
         }
     }